@@ -1,94 +1,8 @@
 use std::time::Duration;
 
+use futures::StreamExt;
 use structopt::StructOpt;
-use surge_ping::{Client, Config, IcmpPacket, PingIdentifier, PingSequence, ICMP};
-use tokio::time;
-
-#[derive(Default, Debug)]
-struct Answer {
-    host: String,
-    transmitted: usize,
-    received: usize,
-    durations: Vec<Duration>,
-}
-impl Answer {
-    fn new(host: &str) -> Answer {
-        Answer {
-            host: host.to_owned(),
-            transmitted: 0,
-            received: 0,
-            durations: Vec::new(),
-        }
-    }
-
-    fn update(&mut self, dur: Option<Duration>) {
-        match dur {
-            Some(dur) => {
-                self.transmitted += 1;
-                self.received += 1;
-                self.durations.push(dur);
-            }
-            None => self.transmitted += 1,
-        }
-    }
-
-    fn min(&self) -> Option<f64> {
-        let min = self
-            .durations
-            .iter()
-            .min()
-            .map(|dur| dur.as_secs_f64() * 1000f64);
-        min
-    }
-
-    fn max(&self) -> Option<f64> {
-        let max = self
-            .durations
-            .iter()
-            .max()
-            .map(|dur| dur.as_secs_f64() * 1000f64);
-        max
-    }
-
-    fn avg(&self) -> Option<f64> {
-        let sum: Duration = self.durations.iter().sum();
-        let avg = sum
-            .checked_div(self.durations.iter().len() as u32)
-            .map(|dur| dur.as_secs_f64() * 1000f64);
-        avg
-    }
-
-    fn mdev(&self) -> Option<f64> {
-        if let Some(avg) = self.avg() {
-            let tmp_sum = self.durations.iter().fold(0_f64, |acc, x| {
-                acc + x.as_secs_f64() * x.as_secs_f64() * 1000000f64
-            });
-            let tmdev = tmp_sum / self.durations.iter().len() as f64 - avg * avg;
-            Some(tmdev.sqrt())
-        } else {
-            None
-        }
-    }
-
-    fn output(&self) {
-        println!("\n--- {} ping statistics ---", self.host);
-        println!(
-            "{} packets transmitted, {} packets received, {:.2}% packet loss",
-            self.transmitted,
-            self.received,
-            (self.transmitted - self.received) as f64 / self.transmitted as f64 * 100_f64
-        );
-        if self.received > 1 {
-            println!(
-                "round-trip min/avg/max/stddev = {:.3}/{:.3}/{:.3}/{:.3} ms",
-                self.min().unwrap(),
-                self.avg().unwrap(),
-                self.max().unwrap(),
-                self.mdev().unwrap()
-            );
-        }
-    }
-}
+use surge_ping::{Client, Config, IcmpPacket, PingIdentifier, PingStats, StreamConfig, ICMP};
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "surge-ping")]
@@ -136,7 +50,6 @@ async fn main() {
         .map(|val| val.ip())
         .unwrap();
 
-    let mut interval = time::interval(Duration::from_millis((opt.interval * 1000f64) as u64));
     let mut config_builder = Config::builder();
     if let Some(interface) = opt.iface {
         config_builder = config_builder.interface(&interface);
@@ -151,11 +64,19 @@ async fn main() {
     let mut pinger = client.pinger(ip, PingIdentifier(111)).await;
     pinger.timeout(Duration::from_secs(opt.timeout));
     let payload = vec![0; opt.size];
-    let mut answer = Answer::new(&opt.host);
+    let mut stats = PingStats::new();
     println!("PING {} ({}): {} data bytes", opt.host, ip, opt.size);
-    for idx in 0..opt.count {
-        interval.tick().await;
-        match pinger.ping(PingSequence(idx), &payload).await {
+    let config = StreamConfig::new()
+        .interval(Duration::from_secs_f64(opt.interval.max(0.0)))
+        .count(opt.count.into())
+        .payload(&payload);
+    let mut results = pinger.stream(config);
+    while let Some((seq, result)) = results.next().await {
+        stats.record(&result);
+        match result.and_then(|(outcome, packet, dur)| {
+            outcome.into_result(seq)?;
+            Ok((packet, dur))
+        }) {
             Ok((IcmpPacket::V4(reply), dur)) => {
                 println!(
                     "{} bytes from {}: icmp_seq={} ttl={:?} time={:0.3?}",
@@ -165,7 +86,6 @@ async fn main() {
                     reply.get_ttl(),
                     dur
                 );
-                answer.update(Some(dur));
             }
             Ok((IcmpPacket::V6(reply), dur)) => {
                 println!(
@@ -176,13 +96,10 @@ async fn main() {
                     reply.get_max_hop_limit(),
                     dur
                 );
-                answer.update(Some(dur));
-            }
-            Err(e) => {
-                println!("{}", e);
-                answer.update(None);
             }
+            Err(e) => println!("{}", e),
         }
     }
-    answer.output();
+    println!("\n--- {} ping statistics ---", opt.host);
+    println!("{}", stats);
 }
@@ -1,10 +1,9 @@
 use std::net::IpAddr;
 use std::time::Duration;
 
-use futures::future::join_all;
+use futures::{future::join_all, StreamExt};
 use rand::random;
-use surge_ping::{Client, Config, IcmpPacket, PingIdentifier, PingSequence, ICMP};
-use tokio::time;
+use surge_ping::{DualStackClient, IcmpPacket, PingIdentifier, StreamConfig};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -18,17 +17,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         "2a02:930::ff76",
         "114.114.114.114",
     ];
-    let client_v4 = Client::new(&Config::default())?;
-    let client_v6 = Client::new(&Config::builder().kind(ICMP::V6).build())?;
+    let client = DualStackClient::default();
     let mut tasks = Vec::new();
     for ip in &ips {
         match ip.parse() {
-            Ok(IpAddr::V4(addr)) => {
-                tasks.push(tokio::spawn(ping(client_v4.clone(), IpAddr::V4(addr))))
-            }
-            Ok(IpAddr::V6(addr)) => {
-                tasks.push(tokio::spawn(ping(client_v6.clone(), IpAddr::V6(addr))))
-            }
+            Ok(addr) => tasks.push(tokio::spawn(ping(client.clone(), addr))),
             Err(e) => println!("{} parse to ipaddr error: {}", ip, e),
         }
     }
@@ -37,17 +30,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 // Ping an address 5 times， and print output message（interval 1s）
-async fn ping(client: Client, addr: IpAddr) {
-    let payload = [0; 56];
-    let mut pinger = client.pinger(addr, PingIdentifier(random())).await;
+async fn ping(client: DualStackClient, addr: IpAddr) {
+    let mut pinger = match client.pinger(addr, PingIdentifier(random())).await {
+        Ok(pinger) => pinger,
+        Err(e) => return println!("{}: {}", addr, e),
+    };
     pinger.timeout(Duration::from_secs(1));
-    let mut interval = time::interval(Duration::from_secs(1));
-    for idx in 0..5 {
-        interval.tick().await;
-        match pinger.ping(PingSequence(idx), &payload).await {
+    let config = StreamConfig::new()
+        .interval(Duration::from_secs(1))
+        .count(5);
+    let mut results = pinger.stream(config);
+    while let Some((seq, result)) = results.next().await {
+        match result.and_then(|(outcome, packet, dur)| {
+            outcome.into_result(seq)?;
+            Ok((packet, dur))
+        }) {
             Ok((IcmpPacket::V4(packet), dur)) => println!(
                 "No.{}: {} bytes from {}: icmp_seq={} ttl={:?} time={:0.2?}",
-                idx,
+                seq,
                 packet.get_size(),
                 packet.get_source(),
                 packet.get_sequence(),
@@ -56,15 +56,15 @@ async fn ping(client: Client, addr: IpAddr) {
             ),
             Ok((IcmpPacket::V6(packet), dur)) => println!(
                 "No.{}: {} bytes from {}: icmp_seq={} hlim={} time={:0.2?}",
-                idx,
+                seq,
                 packet.get_size(),
                 packet.get_source(),
                 packet.get_sequence(),
                 packet.get_max_hop_limit(),
                 dur
             ),
-            Err(e) => println!("No.{}: {} ping {}", idx, pinger.host, e),
+            Err(e) => println!("No.{}: {} ping {}", seq, addr, e),
         };
     }
-    println!("[+] {} done.", pinger.host);
+    println!("[+] {} done.", addr);
 }
@@ -4,7 +4,7 @@ use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 use std::os::windows::io::{AsRawSocket, FromRawSocket, IntoRawSocket, RawSocket};
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     io,
     net::{IpAddr, SocketAddr},
     sync::atomic::{AtomicBool, Ordering},
@@ -14,16 +14,20 @@ use std::{
 
 use parking_lot::Mutex;
 use socket2::{Domain, Protocol, Socket, Type as SockType};
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use tokio::io::Interest;
 use tokio::{
     net::UdpSocket,
-    sync::oneshot,
+    sync::{mpsc, oneshot},
     task::{self, JoinHandle},
 };
 use tracing::debug;
 
 use crate::{
     config::Config,
+    histogram::RttRecorder,
     icmp::{icmpv4::Icmpv4Packet, icmpv6::Icmpv6Packet},
+    sys::{RecvMeta, SendMeta},
     IcmpPacket, PingIdentifier, PingSequence, Pinger, SurgeError, ICMP,
 };
 
@@ -42,14 +46,64 @@ macro_rules! is_linux_icmp_socket {
     };
 }
 
+/// The key the kernel tags the transmit timestamp of a request with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct TxId {
+    /// How often the kernel's counter had been restarted when the request was
+    /// sent, so keys handed out again after a restart never match.
+    generation: u32,
+    key: u32,
+}
+
+#[derive(Debug, Default)]
+struct TxKeys {
+    generation: u32,
+    next: u32,
+}
+
+impl TxKeys {
+    fn take(&mut self) -> TxId {
+        let key = self.next;
+        self.next = key.wrapping_add(1);
+        TxId {
+            generation: self.generation,
+            key,
+        }
+    }
+
+    fn restart(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+        self.next = 0;
+    }
+}
+
 #[derive(Clone)]
 pub struct AsyncSocket {
     inner: Arc<UdpSocket>,
     sock_type: SockType,
+    kind: ICMP,
+    #[cfg_attr(not(any(target_os = "linux", target_os = "android")), allow(dead_code))]
+    recv_err: bool,
+    /// Keys of the transmit timestamps, when those are enabled.
+    tx_id: Option<Arc<Mutex<TxKeys>>>,
+    dont_fragment: bool,
 }
 
 impl AsyncSocket {
     pub fn new(config: &Config) -> io::Result<Self> {
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        if config.mark.is_some() || config.vrf.is_some() || config.netns.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "mark, vrf and netns are only supported on Linux and Android",
+            ));
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let (sock_type, socket) = match &config.netns {
+            Some(netns) => Self::create_socket_in(netns, config)?,
+            None => Self::create_socket(config)?,
+        };
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
         let (sock_type, socket) = Self::create_socket(config)?;
 
         socket.set_nonblocking(true)?;
@@ -60,6 +114,30 @@ impl AsyncSocket {
         if let Some(interface) = &config.interface {
             socket.bind_device(Some(interface.as_bytes()))?;
         }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Some(vrf) = &config.vrf {
+            if config.interface.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "a socket is bound to either an interface or a VRF",
+                ));
+            }
+            socket
+                .bind_device(Some(vrf.as_bytes()))
+                .map_err(|err| match err.raw_os_error() {
+                    Some(libc::ENODEV) => io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("no VRF device named {}", vrf),
+                    ),
+                    _ => needs_capability(err, "binding to a VRF", "CAP_NET_RAW"),
+                })?;
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Some(mark) = config.mark {
+            socket
+                .set_mark(mark)
+                .map_err(|err| needs_capability(err, "setting SO_MARK", "CAP_NET_ADMIN"))?;
+        }
         #[cfg(any(
             target_os = "ios",
             target_os = "visionos",
@@ -85,10 +163,78 @@ impl AsyncSocket {
                 ICMP::V6 => socket.set_unicast_hops_v6(ttl)?,
             }
         }
+        if let Some(tos) = config.tos {
+            match config.kind {
+                ICMP::V4 => socket.set_tos_v4(tos)?,
+                #[cfg(any(
+                    target_os = "android",
+                    target_os = "dragonfly",
+                    target_os = "freebsd",
+                    target_os = "illumos",
+                    target_os = "linux",
+                    target_os = "macos",
+                    target_os = "netbsd",
+                    target_os = "openbsd",
+                ))]
+                ICMP::V6 => socket.set_tclass_v6(tos)?,
+                #[cfg(not(any(
+                    target_os = "android",
+                    target_os = "dragonfly",
+                    target_os = "freebsd",
+                    target_os = "illumos",
+                    target_os = "linux",
+                    target_os = "macos",
+                    target_os = "netbsd",
+                    target_os = "openbsd",
+                )))]
+                ICMP::V6 => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "IPV6_TCLASS is not supported on this platform",
+                    ));
+                }
+            }
+        }
         #[cfg(target_os = "freebsd")]
         if let Some(fib) = config.fib {
             socket.set_fib(fib)?;
         }
+        // Linux ping sockets never hand ICMP errors to `recv_from`, they are
+        // only reported through the socket error queue.
+        let icmp_errors_queued = cfg!(any(target_os = "linux", target_os = "android"))
+            && is_linux_icmp_socket!(sock_type);
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let tx_id = {
+            if icmp_errors_queued {
+                crate::sys::set_recv_err(socket.as_raw_fd(), config.kind)?;
+            }
+            // The IP header does not reach userspace on ping sockets (nor on
+            // any IPv6 socket), so ask for the fields we report as control
+            // messages instead.
+            match config.kind {
+                ICMP::V4 => crate::sys::set_recv_ipv4_info(socket.as_raw_fd())?,
+                ICMP::V6 => crate::sys::set_recv_ipv6_info(socket.as_raw_fd())?,
+            }
+            if config.dont_fragment {
+                crate::sys::set_dont_fragment(socket.as_raw_fd(), config.kind)?;
+            }
+            if config.timestamping && crate::sys::set_timestamping(socket.as_raw_fd())? {
+                Some(Arc::default())
+            } else {
+                None
+            }
+        };
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let tx_id = None;
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        if config.dont_fragment {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "dont_fragment is only supported on Linux and Android",
+            ));
+        }
+        // Transmit timestamps are reported on the error queue as well.
+        let recv_err = icmp_errors_queued || tx_id.is_some();
         #[cfg(windows)]
         let socket = UdpSocket::from_std(unsafe {
             std::net::UdpSocket::from_raw_socket(socket.into_raw_socket())
@@ -99,6 +245,28 @@ impl AsyncSocket {
         Ok(Self {
             inner: Arc::new(socket),
             sock_type,
+            kind: config.kind,
+            recv_err,
+            tx_id,
+            dont_fragment: config.dont_fragment,
+        })
+    }
+
+    /// Create the socket on a thread that joins the network namespace
+    /// `netns` and ends right after, so no other thread changes namespace.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn create_socket_in(netns: &str, config: &Config) -> io::Result<(SockType, Socket)> {
+        let netns_file = crate::sys::open_netns(netns)?;
+        std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    crate::sys::set_netns(&netns_file).map_err(|err| {
+                        needs_capability(err, "entering a network namespace", "CAP_SYS_ADMIN")
+                    })?;
+                    Self::create_socket(config)
+                })
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
         })
     }
 
@@ -155,8 +323,144 @@ impl AsyncSocket {
         self.inner.recv_from(buf).await
     }
 
+    /// Receive the next datagram along with the control messages the
+    /// platform reports for it.
+    pub(crate) async fn recv_msg(&self, buf: &mut [u8]) -> io::Result<RecvMeta> {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            let fd = self.inner.as_raw_fd();
+            self.inner
+                .async_io(Interest::READABLE, || crate::sys::recvmsg(fd, buf, 0))
+                .await
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        {
+            let (len, addr) = self.inner.recv_from(buf).await?;
+            Ok(RecvMeta {
+                len,
+                addr: Some(addr),
+                ..Default::default()
+            })
+        }
+    }
+
+    /// Receive the next message from the socket error queue.
+    ///
+    /// Only Linux ping sockets and sockets with transmit timestamps use the
+    /// error queue; on every other socket this never completes.
+    pub(crate) async fn recv_err(&self, buf: &mut [u8]) -> io::Result<RecvMeta> {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if self.recv_err {
+            let fd = self.inner.as_raw_fd();
+            return self
+                .inner
+                .async_io(Interest::ERROR, || {
+                    crate::sys::recvmsg(fd, buf, libc::MSG_ERRQUEUE)
+                })
+                .await;
+        }
+        let _ = buf;
+        std::future::pending().await
+    }
+
     pub async fn send_to(&self, buf: &mut [u8], target: &SocketAddr) -> io::Result<usize> {
-        self.inner.send_to(buf, target).await
+        self.send_msg(buf, target, &SendMeta::default())
+            .await
+            .map(|(size, _)| size)
+    }
+
+    /// Send a datagram with the per-packet options in `meta` and return the
+    /// key its transmit timestamp will be reported with, if transmit
+    /// timestamps are enabled.
+    ///
+    /// Per-packet options are only supported on Linux and Android.
+    pub(crate) async fn send_msg(
+        &self,
+        buf: &[u8],
+        target: &SocketAddr,
+        meta: &SendMeta,
+    ) -> io::Result<(usize, Option<TxId>)> {
+        match self.send_msg_once(buf, target, meta).await {
+            // An ICMP error for an earlier request leaves a pending error on
+            // the socket, which the next send reports instead of sending.
+            // That clears it, so only a second failure is about this one.
+            Err(err) if is_pending_icmp_error(&err) => {
+                debug!("retrying send after {:?}", err);
+                self.send_msg_once(buf, target, meta).await
+            }
+            result => result,
+        }
+    }
+
+    async fn send_msg_once(
+        &self,
+        buf: &[u8],
+        target: &SocketAddr,
+        meta: &SendMeta,
+    ) -> io::Result<(usize, Option<TxId>)> {
+        if self.tx_id.is_none() && meta.is_empty() {
+            return self
+                .inner
+                .send_to(buf, target)
+                .await
+                .map(|size| (size, None));
+        }
+        loop {
+            self.inner.writable().await?;
+            // The kernel numbers packets in the order they are sent, so keep
+            // our counter in step by never letting two sends race.
+            let mut keys = self.tx_id.as_ref().map(|tx_id| tx_id.lock());
+            let result = self.try_send_msg(buf, target, meta);
+            let id = match (&result, keys.as_deref_mut()) {
+                (Ok(_), Some(keys)) => Some(keys.take()),
+                (Err(err), Some(keys)) if err.kind() != io::ErrorKind::WouldBlock => {
+                    self.restart_tx_ids(keys);
+                    None
+                }
+                _ => None,
+            };
+            match result {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                result => return result.map(|size| (size, id)),
+            }
+        }
+    }
+
+    /// Whether a failed send used up a transmit timestamp key depends on the
+    /// kernel and on where it failed, so start the kernel's keys and ours
+    /// over at 0, in a new generation.
+    fn restart_tx_ids(&self, keys: &mut TxKeys) {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Err(e) = crate::sys::restart_timestamp_ids(self.inner.as_raw_fd()) {
+            debug!("cannot restart transmit timestamp keys: {}", e);
+        }
+        keys.restart();
+    }
+
+    /// The generation of the transmit timestamp keys the kernel reports now.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn tx_generation(&self) -> u32 {
+        self.tx_id
+            .as_ref()
+            .map_or(0, |tx_id| tx_id.lock().generation)
+    }
+
+    fn try_send_msg(&self, buf: &[u8], target: &SocketAddr, meta: &SendMeta) -> io::Result<usize> {
+        if meta.is_empty() {
+            return self.inner.try_send_to(buf, *target);
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            let fd = self.inner.as_raw_fd();
+            self.inner.try_io(Interest::WRITABLE, || {
+                crate::sys::sendmsg(fd, buf, target, meta)
+            })
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "per-packet socket options are not supported on this platform",
+        ))
     }
 
     pub fn local_addr(&self) -> io::Result<SocketAddr> {
@@ -167,6 +471,17 @@ impl AsyncSocket {
         self.sock_type
     }
 
+    /// The address family of the socket.
+    pub fn kind(&self) -> ICMP {
+        self.kind
+    }
+
+    /// Whether requests are sent with Don't Fragment, see
+    /// [`ConfigBuilder::dont_fragment`](crate::ConfigBuilder::dont_fragment).
+    pub fn dont_fragment(&self) -> bool {
+        self.dont_fragment
+    }
+
     #[cfg(unix)]
     pub fn get_native_sock(&self) -> RawFd {
         self.inner.as_raw_fd()
@@ -178,7 +493,27 @@ impl AsyncSocket {
     }
 }
 
-#[derive(PartialEq, Eq, Hash)]
+/// Explain a permission error from an operation that needs `capability`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn needs_capability(err: io::Error, operation: &str, capability: &str) -> io::Error {
+    if err.kind() != io::ErrorKind::PermissionDenied {
+        return err;
+    }
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!(
+            "Permission denied {}, which needs {}. Run with sudo or grant it with \
+            `sudo setcap {}+ep <binary>`\n\
+            Original error: {}",
+            operation,
+            capability,
+            capability.to_lowercase(),
+            err
+        ),
+    )
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 struct ReplyToken(IpAddr, Option<PingIdentifier>, PingSequence);
 
 pub(crate) struct Reply {
@@ -186,16 +521,90 @@ pub(crate) struct Reply {
     pub packet: IcmpPacket,
 }
 
+/// Why a reply arrived that no one was waiting for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StrayKind {
+    /// The request was already answered; the network duplicated the reply.
+    Duplicate,
+    /// The reply arrived after the request timed out.
+    Late,
+}
+
+/// Where a pinger wants replies that match no waiter.
+pub(crate) type StraySender = mpsc::Sender<(StrayKind, Reply)>;
+
+/// How many answered or timed out requests to remember, so that replies that
+/// still turn up for them can be told apart from unsolicited ones.
+const RECENT_TOKENS: usize = 1024;
+
+/// How many transmit timestamps to keep around for pingers that have not
+/// claimed them yet.
+#[cfg_attr(not(any(target_os = "linux", target_os = "android")), allow(dead_code))]
+const MAX_UNCLAIMED_TX_TIMESTAMPS: u32 = 1024;
+
+struct Waiter {
+    reply: oneshot::Sender<Reply>,
+    strays: StraySender,
+}
+
+/// A request that is no longer waited for.
+struct Recent {
+    /// What a reply arriving now would be.
+    kind: StrayKind,
+    strays: StraySender,
+    generation: u64,
+}
+
+#[derive(Default)]
+struct Waiters {
+    pending: HashMap<ReplyToken, Waiter>,
+    recent: HashMap<ReplyToken, Recent>,
+    /// Insertion order of `recent`, oldest first. Tokens that were waited
+    /// for again since carry an outdated generation.
+    recent_order: VecDeque<(ReplyToken, u64)>,
+    generation: u64,
+}
+
+impl Waiters {
+    fn remember(&mut self, token: ReplyToken, kind: StrayKind, strays: StraySender) {
+        self.generation += 1;
+        let generation = self.generation;
+        self.recent.insert(
+            token,
+            Recent {
+                kind,
+                strays,
+                generation,
+            },
+        );
+        self.recent_order.push_back((token, generation));
+        while self.recent_order.len() > RECENT_TOKENS {
+            let Some((token, generation)) = self.recent_order.pop_front() else {
+                break;
+            };
+            if self
+                .recent
+                .get(&token)
+                .is_some_and(|recent| recent.generation == generation)
+            {
+                self.recent.remove(&token);
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct ReplyMap {
-    inner: Arc<Mutex<HashMap<ReplyToken, oneshot::Sender<Reply>>>>,
+    inner: Arc<Mutex<Waiters>>,
+    sent: Arc<Mutex<HashMap<TxId, Instant>>>,
     alive: Arc<AtomicBool>,
 }
 
 impl Default for ReplyMap {
     fn default() -> Self {
         Self {
-            inner: Arc::new(Mutex::new(HashMap::new())),
+            inner: Arc::new(Mutex::new(Waiters::default())),
+            sent: Arc::new(Mutex::new(HashMap::new())),
             alive: Arc::new(AtomicBool::new(true)),
         }
     }
@@ -205,24 +614,29 @@ impl ReplyMap {
     /// Register to wait for a reply from host with ident and sequence number.
     /// If there is already someone waiting for this specific reply then an
     /// error is returned.
+    ///
+    /// Replies that turn up for the same request once it has been answered
+    /// or has timed out are sent to `strays`.
     pub fn new_waiter(
         &self,
         host: IpAddr,
         ident: Option<PingIdentifier>,
         seq: PingSequence,
+        strays: StraySender,
     ) -> Result<oneshot::Receiver<Reply>, SurgeError> {
+        let (tx, rx) = oneshot::channel();
+        let token = ReplyToken(host, ident, seq);
+        let mut waiters = self.inner.lock();
+        // Checked under the lock, so no waiter is added after the client is
+        // destroyed.
         if !self.alive.load(Ordering::Relaxed) {
             return Err(SurgeError::ClientDestroyed);
         }
-        let (tx, rx) = oneshot::channel();
-        if self
-            .inner
-            .lock()
-            .insert(ReplyToken(host, ident, seq), tx)
-            .is_some()
-        {
+        if waiters.pending.contains_key(&token) {
             return Err(SurgeError::IdenticalRequests { host, ident, seq });
         }
+        waiters.recent.remove(&token);
+        waiters.pending.insert(token, Waiter { reply: tx, strays });
         Ok(rx)
     }
 
@@ -233,12 +647,79 @@ impl ReplyMap {
         ident: Option<PingIdentifier>,
         seq: PingSequence,
     ) -> Option<oneshot::Sender<Reply>> {
-        self.inner.lock().remove(&ReplyToken(host, ident, seq))
+        self.inner
+            .lock()
+            .pending
+            .remove(&ReplyToken(host, ident, seq))
+            .map(|waiter| waiter.reply)
+    }
+
+    /// Remove a waiter whose request timed out, remembering it so a reply
+    /// that still arrives is reported as late.
+    pub(crate) fn expire(&self, host: IpAddr, ident: Option<PingIdentifier>, seq: PingSequence) {
+        let token = ReplyToken(host, ident, seq);
+        let mut waiters = self.inner.lock();
+        if let Some(waiter) = waiters.pending.remove(&token) {
+            waiters.remember(token, StrayKind::Late, waiter.strays);
+        }
+    }
+
+    /// Hand a reply to whoever waits for it, or to the pinger whose request
+    /// it answers late or again. The reply is given back if neither exists.
+    pub(crate) fn deliver(
+        &self,
+        host: IpAddr,
+        ident: Option<PingIdentifier>,
+        seq: PingSequence,
+        reply: Reply,
+    ) -> Result<(), Reply> {
+        let token = ReplyToken(host, ident, seq);
+        let mut waiters = self.inner.lock();
+        if let Some(waiter) = waiters.pending.remove(&token) {
+            waiters.remember(token, StrayKind::Duplicate, waiter.strays);
+            drop(waiters);
+            // If send fails the receiving end has closed. Nothing to do.
+            let _ = waiter.reply.send(reply);
+            return Ok(());
+        }
+        match waiters.recent.get_mut(&token) {
+            Some(recent) => {
+                let kind = recent.kind;
+                // Anything after the first late reply is a duplicate of it.
+                recent.kind = StrayKind::Duplicate;
+                // Strays the pinger does not collect are dropped.
+                let _ = recent.strays.try_send((kind, reply));
+                Ok(())
+            }
+            None => Err(reply),
+        }
+    }
+
+    /// Record when the kernel sent the packet with transmit timestamp key `id`.
+    #[cfg_attr(not(any(target_os = "linux", target_os = "android")), allow(dead_code))]
+    pub(crate) fn record_sent(&self, id: TxId, timestamp: Instant) {
+        let mut sent = self.sent.lock();
+        // Timestamps that arrive after their pinger gave up are never claimed.
+        if sent.len() as u32 >= 2 * MAX_UNCLAIMED_TX_TIMESTAMPS {
+            sent.retain(|old, _| {
+                old.generation == id.generation
+                    && id.key.wrapping_sub(old.key) < MAX_UNCLAIMED_TX_TIMESTAMPS
+            });
+        }
+        sent.insert(id, timestamp);
+    }
+
+    /// Claim the transmit timestamp recorded for key `id`.
+    pub(crate) fn take_sent(&self, id: TxId) -> Option<Instant> {
+        self.sent.lock().remove(&id)
     }
 
     /// Mark the client as destroyed. This is called when the Client is dropped.
     pub(crate) fn mark_destroyed(&self) {
+        let mut waiters = self.inner.lock();
         self.alive.store(false, Ordering::Relaxed);
+        // Dropping their senders fails the pending requests.
+        waiters.pending.clear();
     }
 }
 
@@ -251,15 +732,16 @@ pub struct Client {
     socket: AsyncSocket,
     reply_map: ReplyMap,
     recv: Arc<JoinHandle<()>>,
+    histogram: Arc<Mutex<Option<RttRecorder>>>,
 }
 
 impl Drop for Client {
     fn drop(&mut self) {
-        // Mark the reply_map as destroyed so any pending or new ping operations
-        // will fail with ClientDestroyed error instead of timing out.
-        self.reply_map.mark_destroyed();
         // The client may pass through multiple tasks, so need to judge whether the number of references is 1.
         if Arc::strong_count(&self.recv) <= 1 {
+            // Mark the reply_map as destroyed so any pending or new ping operations
+            // will fail with ClientDestroyed error instead of timing out.
+            self.reply_map.mark_destroyed();
             self.recv.abort();
         }
     }
@@ -276,12 +758,26 @@ impl Client {
             socket,
             reply_map,
             recv: Arc::new(recv),
+            histogram: Arc::default(),
         })
     }
 
     /// Create a `Pinger` instance, you can make special configuration for this instance.
     pub async fn pinger(&self, host: IpAddr, ident: PingIdentifier) -> Pinger {
-        Pinger::new(host, ident, self.socket.clone(), self.reply_map.clone())
+        Pinger::new(
+            host,
+            ident,
+            self.socket.clone(),
+            self.reply_map.clone(),
+            self.histogram.clone(),
+        )
+    }
+
+    /// Record the round trip time of every echo reply, to any host, in
+    /// `recorder`. This applies to all clones of the client and the pingers
+    /// created from them, including existing ones.
+    pub fn histogram(&self, recorder: RttRecorder) {
+        *self.histogram.lock() = Some(recorder);
     }
 
     /// Expose the underlying socket, if user wants to modify any options on it
@@ -291,48 +787,327 @@ impl Client {
 }
 
 async fn recv_task(socket: AsyncSocket, reply_map: ReplyMap) {
-    let mut buf = [0; 2048];
+    // Large enough for any IP datagram, so echoed data is never truncated.
+    let mut buf = vec![0; 65536];
+    let mut err_buf = [0; 2048];
     loop {
-        if let Ok((sz, addr)) = socket.recv_from(&mut buf).await {
-            let timestamp = Instant::now();
-            let message = &buf[..sz];
-            let local_addr = socket.local_addr().unwrap().ip();
-            let packet = {
-                let result = match addr.ip() {
-                    IpAddr::V4(src_addr) => {
-                        let local_addr_ip4 = match local_addr {
-                            IpAddr::V4(local_addr_ip4) => local_addr_ip4,
-                            _ => continue,
-                        };
-
-                        Icmpv4Packet::decode(message, socket.sock_type, src_addr, local_addr_ip4)
-                            .map(IcmpPacket::V4)
-                    }
-                    IpAddr::V6(src_addr) => {
-                        Icmpv6Packet::decode(message, src_addr).map(IcmpPacket::V6)
-                    }
-                };
+        tokio::select! {
+            // Poll the error queue first: a transmit timestamp is always
+            // queued before the reply to that request can arrive.
+            biased;
+
+            result = socket.recv_err(&mut err_buf) => {
                 match result {
-                    Ok(packet) => packet,
-                    Err(err) => {
-                        debug!("error decoding ICMP packet: {:?}", err);
-                        continue;
+                    Ok(meta) => {
+                        let timestamp = received_at(&meta);
+                        if record_tx_timestamp(&socket, &reply_map, &meta) {
+                            continue;
+                        }
+                        if let Some(mut packet) = decode_queued_error(&socket, &err_buf, &meta) {
+                            apply_recv_meta(&mut packet, &meta);
+                            dispatch(&socket, &reply_map, packet, timestamp);
+                        }
+                    }
+                    Err(err) => debug!("error reading socket error queue: {:?}", err),
+                }
+            }
+            result = socket.recv_msg(&mut buf) => {
+                if let Ok(meta) = result {
+                    let timestamp = received_at(&meta);
+                    let Some(addr) = meta.addr else { continue };
+                    let message = &buf[..meta.len.min(buf.len())];
+                    if let Some(mut packet) = decode_packet(&socket, message, addr.ip()) {
+                        apply_recv_meta(&mut packet, &meta);
+                        dispatch(&socket, &reply_map, packet, timestamp);
                     }
                 }
+            }
+        }
+    }
+}
+
+fn decode_packet(socket: &AsyncSocket, message: &[u8], src_addr: IpAddr) -> Option<IcmpPacket> {
+    let local_addr = socket.local_addr().ok()?.ip();
+    let result = match src_addr {
+        IpAddr::V4(src_addr) => {
+            let local_addr_ip4 = match local_addr {
+                IpAddr::V4(local_addr_ip4) => local_addr_ip4,
+                _ => return None,
             };
 
-            let ident = if is_linux_icmp_socket!(socket.get_type()) {
-                None
-            } else {
-                Some(packet.get_identifier())
+            Icmpv4Packet::decode(message, socket.sock_type, src_addr, local_addr_ip4)
+                .map(IcmpPacket::V4)
+        }
+        IpAddr::V6(src_addr) => Icmpv6Packet::decode(message, src_addr).map(IcmpPacket::V6),
+    };
+    match result {
+        Ok(packet) => Some(packet),
+        Err(err) => {
+            debug!("error decoding ICMP packet: {:?}", err);
+            None
+        }
+    }
+}
+
+/// Whether a send failed with the kind of error an ICMP error for an earlier
+/// request leaves pending on the socket, rather than because of this one.
+fn is_pending_icmp_error(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    return matches!(
+        err.raw_os_error(),
+        Some(
+            libc::EHOSTUNREACH
+                | libc::ENETUNREACH
+                | libc::ECONNREFUSED
+                | libc::EMSGSIZE
+                | libc::EHOSTDOWN
+        )
+    );
+    #[cfg(not(unix))]
+    {
+        let _ = err;
+        false
+    }
+}
+
+/// When the packet was received: the kernel timestamp if there is one, else
+/// now.
+fn received_at(meta: &RecvMeta) -> Instant {
+    meta.timestamp
+        .map(crate::sys::to_instant)
+        .unwrap_or_else(Instant::now)
+}
+
+/// Record a transmit timestamp read from the socket error queue. Returns
+/// whether the message was one.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn record_tx_timestamp(socket: &AsyncSocket, reply_map: &ReplyMap, meta: &RecvMeta) -> bool {
+    match meta.extended_err {
+        Some(err) if err.origin == crate::sys::ORIGIN_TIMESTAMPING => {
+            if let Some(sent) = meta.timestamp {
+                let id = TxId {
+                    generation: socket.tx_generation(),
+                    key: err.data,
+                };
+                reply_map.record_sent(id, crate::sys::to_instant(sent));
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn record_tx_timestamp(_socket: &AsyncSocket, _reply_map: &ReplyMap, _meta: &RecvMeta) -> bool {
+    false
+}
+
+/// Turn an ICMP error read from the socket error queue into a packet. The
+/// queued message is the echo request we sent, the error itself is described
+/// by the `sock_extended_err` control message.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn decode_queued_error(socket: &AsyncSocket, buf: &[u8], meta: &RecvMeta) -> Option<IcmpPacket> {
+    use crate::sys::{ORIGIN_ICMP, ORIGIN_ICMP6};
+    use std::net::Ipv6Addr;
+
+    let err = meta.extended_err?;
+    let real_dest = meta.addr?.ip();
+    let message = &buf[..meta.len.min(buf.len())];
+    let local_addr = socket.local_addr().ok()?.ip();
+    let result = match (err.origin, real_dest, local_addr) {
+        (ORIGIN_ICMP, IpAddr::V4(real_dest), IpAddr::V4(local_addr)) => {
+            let src_addr = match err.offender {
+                Some(IpAddr::V4(offender)) => offender,
+                _ => real_dest,
+            };
+            Icmpv4Packet::decode_from_error_queue(
+                message,
+                err.icmp_type,
+                err.icmp_code,
+                err.info,
+                src_addr,
+                local_addr,
+                real_dest,
+            )
+            .map(IcmpPacket::V4)
+        }
+        (ORIGIN_ICMP6, IpAddr::V6(real_dest), local_addr) => {
+            let src_addr = match err.offender {
+                Some(IpAddr::V6(offender)) => offender,
+                _ => real_dest,
+            };
+            let dst_addr = match (meta.dst_addr, local_addr) {
+                (Some(IpAddr::V6(dst_addr)), _) | (_, IpAddr::V6(dst_addr)) => dst_addr,
+                _ => Ipv6Addr::UNSPECIFIED,
             };
+            Icmpv6Packet::decode_from_error_queue(
+                message,
+                err.icmp_type,
+                err.icmp_code,
+                err.info,
+                src_addr,
+                dst_addr,
+                real_dest,
+            )
+            .map(IcmpPacket::V6)
+        }
+        _ => {
+            debug!("ignoring non-ICMP error queue message: {:?}", err);
+            return None;
+        }
+    };
+    match result {
+        Ok(packet) => Some(packet),
+        Err(err) => {
+            debug!("error decoding queued ICMP error: {:?}", err);
+            None
+        }
+    }
+}
 
-            if let Some(waiter) = reply_map.remove(addr.ip(), ident, packet.get_sequence()) {
-                // If send fails the receiving end has closed. Nothing to do.
-                let _ = waiter.send(Reply { timestamp, packet });
-            } else {
-                debug!("no one is waiting for ICMP packet ({:?})", packet);
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn decode_queued_error(_socket: &AsyncSocket, _buf: &[u8], _meta: &RecvMeta) -> Option<IcmpPacket> {
+    None
+}
+
+/// Fill in the packet fields that only arrive as control messages.
+fn apply_recv_meta(packet: &mut IcmpPacket, meta: &RecvMeta) {
+    match packet {
+        IcmpPacket::V4(packet) => {
+            if let Some(ttl) = meta.hop_limit {
+                packet.ttl(ttl);
+            }
+            if let Some(tos) = meta.tos {
+                packet.tos(tos);
+            }
+        }
+        IcmpPacket::V6(packet) => {
+            if let Some(hop_limit) = meta.hop_limit {
+                packet.max_hop_limit(hop_limit);
             }
+            if let Some(traffic_class) = meta.tos {
+                packet.traffic_class(traffic_class);
+            }
+        }
+    }
+}
+
+fn dispatch(socket: &AsyncSocket, reply_map: &ReplyMap, packet: IcmpPacket, timestamp: Instant) {
+    let ident = if is_linux_icmp_socket!(socket.get_type()) {
+        None
+    } else {
+        Some(packet.get_identifier())
+    };
+
+    // Error messages come from whichever router generated them, so route by
+    // the destination quoted in the packet instead of the sender's address.
+    let host = packet.get_real_dest();
+    let seq = packet.get_sequence();
+    if let Err(reply) = reply_map.deliver(host, ident, seq, Reply { timestamp, packet }) {
+        debug!("no one is waiting for ICMP packet ({:?})", reply.packet);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn retry_only_pending_icmp_errors() {
+        for errno in [libc::EHOSTUNREACH, libc::ECONNREFUSED, libc::EMSGSIZE] {
+            assert!(is_pending_icmp_error(&io::Error::from_raw_os_error(errno)));
+        }
+        for errno in [libc::EINVAL, libc::EPERM, libc::EACCES] {
+            assert!(!is_pending_icmp_error(&io::Error::from_raw_os_error(errno)));
+        }
+        assert!(!is_pending_icmp_error(&io::ErrorKind::Unsupported.into()));
+    }
+
+    #[test]
+    fn tx_keys_after_restart() {
+        let reply_map = ReplyMap::default();
+        let mut keys = TxKeys::default();
+        let before = keys.take();
+        keys.restart();
+        let after = keys.take();
+        assert_eq!((before.key, after.key), (0, 0));
+
+        // The timestamp of the new request never answers for the old one.
+        let sent = Instant::now();
+        reply_map.record_sent(after, sent);
+        assert_eq!(reply_map.take_sent(before), None);
+        assert_eq!(reply_map.take_sent(after), Some(sent));
+    }
+
+    fn reply(seq: u16) -> Reply {
+        let buf = [129, 0, 0, 0, 0x12, 0x34, 0, seq as u8];
+        let packet = Icmpv6Packet::decode(&buf, "2001:db8::8".parse().unwrap()).unwrap();
+        Reply {
+            timestamp: Instant::now(),
+            packet: IcmpPacket::V6(packet),
         }
     }
+
+    #[test]
+    fn duplicate_and_late_replies() {
+        let reply_map = ReplyMap::default();
+        let host = "2001:db8::8".parse().unwrap();
+        let (strays, mut stray_rx) = mpsc::channel(8);
+
+        // Answered, then duplicated.
+        let mut waiter = reply_map
+            .new_waiter(host, None, PingSequence(1), strays.clone())
+            .unwrap();
+        assert!(reply_map
+            .deliver(host, None, PingSequence(1), reply(1))
+            .is_ok());
+        assert!(waiter.try_recv().is_ok());
+        assert!(reply_map
+            .deliver(host, None, PingSequence(1), reply(1))
+            .is_ok());
+        let (kind, _) = stray_rx.try_recv().unwrap();
+        assert_eq!(kind, StrayKind::Duplicate);
+
+        // Timed out, then answered late, then duplicated.
+        let _waiter = reply_map
+            .new_waiter(host, None, PingSequence(2), strays)
+            .unwrap();
+        reply_map.expire(host, None, PingSequence(2));
+        assert!(reply_map
+            .deliver(host, None, PingSequence(2), reply(2))
+            .is_ok());
+        assert!(reply_map
+            .deliver(host, None, PingSequence(2), reply(2))
+            .is_ok());
+        assert_eq!(stray_rx.try_recv().unwrap().0, StrayKind::Late);
+        assert_eq!(stray_rx.try_recv().unwrap().0, StrayKind::Duplicate);
+
+        // Never asked for.
+        assert!(reply_map
+            .deliver(host, None, PingSequence(3), reply(3))
+            .is_err());
+        assert!(stray_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn destroyed_with_pending_waiters() {
+        let reply_map = ReplyMap::default();
+        let host = "2001:db8::8".parse().unwrap();
+        let (strays, _stray_rx) = mpsc::channel(8);
+
+        let mut waiter = reply_map
+            .new_waiter(host, None, PingSequence(1), strays.clone())
+            .unwrap();
+        reply_map.mark_destroyed();
+        // The pending request fails instead of waiting for its timeout.
+        assert!(matches!(
+            waiter.try_recv(),
+            Err(oneshot::error::TryRecvError::Closed)
+        ));
+        assert!(matches!(
+            reply_map.new_waiter(host, None, PingSequence(2), strays),
+            Err(SurgeError::ClientDestroyed)
+        ));
+    }
 }
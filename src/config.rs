@@ -14,7 +14,13 @@ pub struct Config {
     pub interface: Option<String>,
     pub interface_index: Option<NonZeroU32>,
     pub ttl: Option<u32>,
+    pub tos: Option<u32>,
     pub fib: Option<u32>,
+    pub mark: Option<u32>,
+    pub vrf: Option<String>,
+    pub netns: Option<String>,
+    pub timestamping: bool,
+    pub dont_fragment: bool,
 }
 
 impl Default for Config {
@@ -26,7 +32,13 @@ impl Default for Config {
             interface: None,
             interface_index: None,
             ttl: None,
+            tos: None,
             fib: None,
+            mark: None,
+            vrf: None,
+            netns: None,
+            timestamping: false,
+            dont_fragment: false,
         }
     }
 }
@@ -50,7 +62,13 @@ pub struct ConfigBuilder {
     interface: Option<String>,
     interface_index: Option<NonZeroU32>,
     ttl: Option<u32>,
+    tos: Option<u32>,
     fib: Option<u32>,
+    mark: Option<u32>,
+    vrf: Option<String>,
+    netns: Option<String>,
+    timestamping: bool,
+    dont_fragment: bool,
 }
 
 impl Default for ConfigBuilder {
@@ -62,7 +80,13 @@ impl Default for ConfigBuilder {
             interface: None,
             interface_index: None,
             ttl: None,
+            tos: None,
             fib: None,
+            mark: None,
+            vrf: None,
+            netns: None,
+            timestamping: false,
+            dont_fragment: false,
         }
     }
 }
@@ -102,11 +126,80 @@ impl ConfigBuilder {
         self
     }
 
+    /// Set the value of the `IP_TOS` option, or `IPV6_TCLASS` for IPv6, for
+    /// this socket.
+    ///
+    /// This value sets the type of service (DSCP and ECN) field, or the
+    /// traffic class, of every packet sent from this socket; e.g. `0xb8` for
+    /// DSCP EF. Use [`Pinger::tos`](crate::Pinger::tos) to override it for
+    /// one pinger.
+    pub fn tos(mut self, tos: u32) -> Self {
+        self.tos = Some(tos);
+        self
+    }
+
     pub fn fib(mut self, fib: u32) -> Self {
         self.fib = Some(fib);
         self
     }
 
+    /// Set the value of the `SO_MARK` option for this socket.
+    ///
+    /// The mark (fwmark) of every packet sent from this socket, which policy
+    /// routing rules (`ip rule add fwmark ...`) and netfilter can match on.
+    /// Needs `CAP_NET_ADMIN`. Only supported on Linux and Android; elsewhere
+    /// creating the client fails.
+    pub fn mark(mut self, mark: u32) -> Self {
+        self.mark = Some(mark);
+        self
+    }
+
+    /// Bind this socket to the VRF device `vrf`, so requests are routed with
+    /// the VRF's routing table and only replies arriving in the VRF are
+    /// processed.
+    ///
+    /// This replaces [`ConfigBuilder::interface`], which can name an interface
+    /// enslaved to the VRF instead. Only supported on Linux and Android;
+    /// elsewhere creating the client fails.
+    pub fn vrf(mut self, vrf: &str) -> Self {
+        self.vrf = Some(vrf.to_string());
+        self
+    }
+
+    /// Open the socket in the named network namespace, as created by `ip
+    /// netns add` under `/var/run/netns`.
+    ///
+    /// Only a short-lived thread enters the namespace to open the socket, so
+    /// the rest of the process stays where it is. Needs `CAP_SYS_ADMIN`. Only
+    /// supported on Linux and Android; elsewhere creating the client fails.
+    pub fn netns(mut self, netns: &str) -> Self {
+        self.netns = Some(netns.to_string());
+        self
+    }
+
+    /// Measure round-trip times with kernel timestamps (default: false).
+    ///
+    /// On Linux this enables `SO_TIMESTAMPING` (or `SO_TIMESTAMPNS` where it is
+    /// unavailable), so replies are stamped when the kernel receives them and
+    /// requests when they are sent, instead of whenever the runtime gets
+    /// around to it. Ignored on other platforms.
+    pub fn timestamping(mut self, timestamping: bool) -> Self {
+        self.timestamping = timestamping;
+        self
+    }
+
+    /// Never fragment requests (default: false).
+    ///
+    /// Sets Don't Fragment on IPv4 and `IPV6_DONTFRAG` on IPv6, and ignores
+    /// the path MTU the kernel has cached, so requests too large for a link
+    /// on the path are answered with `Packet Too Big` instead. Needed by
+    /// [`Pinger::discover_mtu`](crate::Pinger::discover_mtu). Only supported
+    /// on Linux and Android; elsewhere creating the client fails.
+    pub fn dont_fragment(mut self, dont_fragment: bool) -> Self {
+        self.dont_fragment = dont_fragment;
+        self
+    }
+
     /// Identify which ICMP the socket handles.(default: ICMP::V4)
     pub fn kind(mut self, kind: ICMP) -> Self {
         self.kind = kind;
@@ -127,7 +220,13 @@ impl ConfigBuilder {
             interface: self.interface,
             interface_index: self.interface_index,
             ttl: self.ttl,
+            tos: self.tos,
             fib: self.fib,
+            mark: self.mark,
+            vrf: self.vrf,
+            netns: self.netns,
+            timestamping: self.timestamping,
+            dont_fragment: self.dont_fragment,
         }
     }
 }
@@ -146,7 +245,13 @@ mod tests {
         assert!(config.interface.is_none());
         assert!(config.interface_index.is_none());
         assert!(config.ttl.is_none());
+        assert!(config.tos.is_none());
         assert!(config.fib.is_none());
+        assert!(config.mark.is_none());
+        assert!(config.vrf.is_none());
+        assert!(config.netns.is_none());
+        assert!(!config.timestamping);
+        assert!(!config.dont_fragment);
     }
 
     #[test]
@@ -161,6 +266,12 @@ mod tests {
         assert_eq!(config.ttl, Some(64));
     }
 
+    #[test]
+    fn test_config_builder_tos() {
+        let config = ConfigBuilder::default().tos(0xb8).build();
+        assert_eq!(config.tos, Some(0xb8));
+    }
+
     #[test]
     fn test_config_builder_bind() {
         let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0);
@@ -186,6 +297,30 @@ mod tests {
         assert_eq!(config.fib, Some(100));
     }
 
+    #[test]
+    fn test_config_builder_routing() {
+        let config = ConfigBuilder::default()
+            .mark(0x10)
+            .vrf("blue")
+            .netns("edge")
+            .build();
+        assert_eq!(config.mark, Some(0x10));
+        assert_eq!(config.vrf, Some("blue".to_string()));
+        assert_eq!(config.netns, Some("edge".to_string()));
+    }
+
+    #[test]
+    fn test_config_builder_timestamping() {
+        let config = ConfigBuilder::default().timestamping(true).build();
+        assert!(config.timestamping);
+    }
+
+    #[test]
+    fn test_config_builder_dont_fragment() {
+        let config = ConfigBuilder::default().dont_fragment(true).build();
+        assert!(config.dont_fragment);
+    }
+
     #[test]
     fn test_config_builder_interface_index() {
         let index = NonZeroU32::new(1).unwrap();
@@ -0,0 +1,100 @@
+use std::{io, net::IpAddr, sync::Arc};
+
+use parking_lot::Mutex;
+
+use crate::{client::Client, config::Config, icmp::PingIdentifier, ping::Pinger, ICMP};
+
+/// A [`Client`] that is only opened when first needed.
+struct LazyClient {
+    config: Config,
+    client: Mutex<Option<Client>>,
+}
+
+impl LazyClient {
+    fn new(mut config: Config, kind: ICMP) -> LazyClient {
+        config.kind = kind;
+        LazyClient {
+            config,
+            client: Mutex::new(None),
+        }
+    }
+
+    fn get(&self) -> io::Result<Client> {
+        let mut client = self.client.lock();
+        if let Some(client) = &*client {
+            return Ok(client.clone());
+        }
+        let opened = Client::new(&self.config)?;
+        *client = Some(opened.clone());
+        Ok(opened)
+    }
+}
+
+/// A client for hosts of both address families, with one IPv4 and one IPv6
+/// [`Client`] behind it.
+///
+/// Each socket, and the task receiving its replies, is only opened when the
+/// first host of its family is pinged, so a machine without IPv6 can still
+/// ping IPv4 hosts. A socket that fails to open is tried again next time.
+/// Clones share the same sockets.
+#[derive(Clone)]
+pub struct DualStackClient {
+    v4: Arc<LazyClient>,
+    v6: Arc<LazyClient>,
+}
+
+impl Default for DualStackClient {
+    fn default() -> Self {
+        DualStackClient::new(Config::default(), Config::default())
+    }
+}
+
+impl DualStackClient {
+    /// Open the IPv4 socket with `v4` and the IPv6 one with `v6` once they
+    /// are needed. Their `kind` is ignored.
+    pub fn new(v4: Config, v6: Config) -> DualStackClient {
+        DualStackClient {
+            v4: Arc::new(LazyClient::new(v4, ICMP::V4)),
+            v6: Arc::new(LazyClient::new(v6, ICMP::V6)),
+        }
+    }
+
+    /// Create a `Pinger` for `host` on the client of its address family,
+    /// opening that client if needed. IPv4-mapped IPv6 addresses such as
+    /// `::ffff:192.0.2.1` are pinged over IPv4.
+    pub async fn pinger(&self, host: IpAddr, ident: PingIdentifier) -> io::Result<Pinger> {
+        let host = host.to_canonical();
+        Ok(self.client(host)?.pinger(host, ident).await)
+    }
+
+    /// The client for hosts of `host`'s address family, opening it if needed,
+    /// e.g. to attach a histogram or get at its socket.
+    pub fn client(&self, host: IpAddr) -> io::Result<Client> {
+        match host.to_canonical() {
+            IpAddr::V4(_) => self.v4.get(),
+            IpAddr::V6(_) => self.v6.get(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn opens_lazily() {
+        let client = DualStackClient::default();
+        assert!(client.v4.client.lock().is_none());
+        assert!(client.v6.client.lock().is_none());
+
+        client
+            .pinger("127.0.0.1".parse().unwrap(), PingIdentifier(1))
+            .await
+            .unwrap();
+        assert!(client.v4.client.lock().is_some());
+        assert!(client.v6.client.lock().is_none());
+
+        // Clones share the opened socket.
+        assert!(client.clone().v4.client.lock().is_some());
+    }
+}
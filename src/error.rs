@@ -3,7 +3,10 @@ use std::{io, net::IpAddr};
 
 use thiserror::Error;
 
-use crate::{icmp::PingSequence, PingIdentifier};
+use crate::{
+    icmp::{message::UnreachableCode, PingSequence},
+    PingIdentifier,
+};
 
 pub type Result<T> = std::result::Result<T, SurgeError>;
 
@@ -19,6 +22,35 @@ pub enum SurgeError {
     IOError(#[from] io::Error),
     #[error("Request timeout for icmp_seq {seq}")]
     Timeout { seq: PingSequence },
+    #[error("From {from} icmp_seq={seq} {code}")]
+    DestinationUnreachable {
+        seq: PingSequence,
+        code: UnreachableCode,
+        from: IpAddr,
+    },
+    #[error("From {from} icmp_seq={seq} wrong data byte #{offset}")]
+    CorruptedReply {
+        seq: PingSequence,
+        offset: usize,
+        from: IpAddr,
+    },
+    #[error("From {from} icmp_seq={seq} Time Exceeded")]
+    TimeExceeded { seq: PingSequence, from: IpAddr },
+    #[error("From {from} icmp_seq={seq} Parameter Problem")]
+    ParameterProblem { seq: PingSequence, from: IpAddr },
+    #[error("From {from} icmp_seq={seq} Packet Too Big (mtu = {mtu})")]
+    PacketTooBig {
+        seq: PingSequence,
+        mtu: u32,
+        from: IpAddr,
+    },
+    #[error("From {from} icmp_seq={seq} unexpected ICMP type {icmp_type} code {code}")]
+    UnexpectedReply {
+        seq: PingSequence,
+        icmp_type: u8,
+        code: u8,
+        from: IpAddr,
+    },
     #[error("Echo Request packet.")]
     EchoRequestPacket,
     #[error("Network error.")]
@@ -31,6 +63,14 @@ pub enum SurgeError {
     },
     #[error("Client has been destroyed, ping operations are no longer available")]
     ClientDestroyed,
+    #[error("{host} is not of the client's address family")]
+    AddressFamilyMismatch { host: IpAddr },
+    #[error("cannot resolve {host}: {source}")]
+    ResolveFailed {
+        host: String,
+        #[source]
+        source: io::Error,
+    },
 }
 
 #[derive(Error, Debug)]
@@ -45,6 +85,26 @@ pub enum MalformedPacketError {
     NotIcmpv6Packet,
     #[error("payload too short, got {got}, want {want}")]
     PayloadTooShort { got: usize, want: usize },
+    #[error("quoted packet is not an ICMP echo request")]
+    QuotedNotEchoRequest,
+}
+
+/// An error in a target specification, see [`TargetList`](crate::TargetList).
+#[derive(Error, Debug)]
+pub enum TargetSpecError {
+    #[error("invalid address in target `{0}`")]
+    InvalidAddress(String),
+    #[error("invalid prefix length in target `{0}`")]
+    InvalidPrefix(String),
+    #[error("range `{0}` ends before it starts or mixes address families")]
+    InvalidRange(String),
+    #[error("line {line}: {source}")]
+    Line {
+        line: usize,
+        source: Box<TargetSpecError>,
+    },
+    #[error("io error: {0}")]
+    IOError(#[from] io::Error),
 }
 
 #[cfg(test)]
@@ -67,6 +127,20 @@ mod tests {
             err.to_string(),
             "Client has been destroyed, ping operations are no longer available"
         );
+
+        let err = SurgeError::AddressFamilyMismatch {
+            host: "::1".parse().unwrap(),
+        };
+        assert_eq!(err.to_string(), "::1 is not of the client's address family");
+
+        let err = SurgeError::ResolveFailed {
+            host: "example.test".to_string(),
+            source: io::ErrorKind::NotFound.into(),
+        };
+        assert_eq!(
+            err.to_string(),
+            "cannot resolve example.test: entity not found"
+        );
     }
 
     #[test]
@@ -75,6 +149,46 @@ mod tests {
         assert_eq!(err.to_string(), "Request timeout for icmp_seq 5");
     }
 
+    #[test]
+    fn test_surge_error_icmp_errors() {
+        let from: IpAddr = "10.0.0.1".parse().unwrap();
+        let err = SurgeError::DestinationUnreachable {
+            seq: PingSequence(3),
+            code: UnreachableCode::V4(1.into()),
+            from,
+        };
+        assert_eq!(
+            err.to_string(),
+            "From 10.0.0.1 icmp_seq=3 Destination Host Unreachable"
+        );
+
+        let err = SurgeError::CorruptedReply {
+            seq: PingSequence(3),
+            offset: 8,
+            from,
+        };
+        assert_eq!(
+            err.to_string(),
+            "From 10.0.0.1 icmp_seq=3 wrong data byte #8"
+        );
+
+        let err = SurgeError::TimeExceeded {
+            seq: PingSequence(3),
+            from,
+        };
+        assert_eq!(err.to_string(), "From 10.0.0.1 icmp_seq=3 Time Exceeded");
+
+        let err = SurgeError::PacketTooBig {
+            seq: PingSequence(3),
+            mtu: 1400,
+            from,
+        };
+        assert_eq!(
+            err.to_string(),
+            "From 10.0.0.1 icmp_seq=3 Packet Too Big (mtu = 1400)"
+        );
+    }
+
     #[test]
     fn test_surge_error_identical_requests() {
         let host: IpAddr = "192.168.1.1".parse().unwrap();
@@ -102,6 +216,9 @@ mod tests {
 
         let err = MalformedPacketError::NotIcmpv6Packet;
         assert_eq!(err.to_string(), "expected an Icmpv6Packet");
+
+        let err = MalformedPacketError::QuotedNotEchoRequest;
+        assert_eq!(err.to_string(), "quoted packet is not an ICMP echo request");
     }
 
     #[test]
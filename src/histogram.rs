@@ -0,0 +1,259 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use parking_lot::Mutex;
+
+/// Linear sub-buckets per power of two, which bounds the error of a
+/// percentile to 1/32 (about 3%) of its value.
+const SUB_BITS: u32 = 5;
+const SUB_BUCKETS: usize = 1 << SUB_BITS;
+/// The last power of two with its own buckets starts at 2^36µs, about 19
+/// hours; anything longer is counted in the last bucket.
+const MAX_SHIFT: u32 = 31;
+const BUCKETS: usize = SUB_BUCKETS * (MAX_SHIFT as usize + 2);
+
+/// Bucket holding a round trip time of `micros` microseconds.
+fn bucket(micros: u64) -> usize {
+    if micros < SUB_BUCKETS as u64 {
+        return micros as usize;
+    }
+    let shift = 63 - micros.leading_zeros() - SUB_BITS;
+    if shift > MAX_SHIFT {
+        return BUCKETS - 1;
+    }
+    SUB_BUCKETS * (shift as usize + 1) + (micros >> shift) as usize - SUB_BUCKETS
+}
+
+/// Range of round trip times, in microseconds, counted in bucket `index`.
+fn bucket_range(index: usize) -> (u64, u64) {
+    if index < SUB_BUCKETS {
+        return (index as u64, index as u64 + 1);
+    }
+    let shift = index / SUB_BUCKETS - 1;
+    let low = ((SUB_BUCKETS + index % SUB_BUCKETS) as u64) << shift;
+    (low, low + (1 << shift))
+}
+
+/// A histogram of round trip times with log-linear buckets, for latency
+/// percentiles such as p50 or p99.
+///
+/// Times are bucketed at microsecond resolution: exactly below 32µs, and to
+/// within about 3% above that. The buckets are allocated once, so recording
+/// costs no allocation. Use [`RttRecorder`] to share one between pingers.
+#[derive(Clone)]
+pub struct RttHistogram {
+    counts: Box<[u64; BUCKETS]>,
+    count: u64,
+    sum: Duration,
+    min: Option<Duration>,
+    max: Option<Duration>,
+}
+
+impl Default for RttHistogram {
+    fn default() -> Self {
+        Self {
+            counts: Box::new([0; BUCKETS]),
+            count: 0,
+            sum: Duration::ZERO,
+            min: None,
+            max: None,
+        }
+    }
+}
+
+impl fmt::Debug for RttHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RttHistogram")
+            .field("count", &self.count)
+            .field("min", &self.min)
+            .field("max", &self.max)
+            .field("p50", &self.percentile(50.0))
+            .field("p99", &self.percentile(99.0))
+            .finish()
+    }
+}
+
+impl RttHistogram {
+    pub fn new() -> RttHistogram {
+        RttHistogram::default()
+    }
+
+    pub fn record(&mut self, rtt: Duration) {
+        let micros = u64::try_from(rtt.as_micros()).unwrap_or(u64::MAX);
+        self.counts[bucket(micros)] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(rtt);
+        self.min = Some(self.min.map_or(rtt, |min| min.min(rtt)));
+        self.max = Some(self.max.map_or(rtt, |max| max.max(rtt)));
+    }
+
+    /// Add every sample of `other` to this histogram.
+    pub fn merge(&mut self, other: &RttHistogram) {
+        for (count, other) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other;
+        }
+        self.count += other.count;
+        self.sum = self.sum.saturating_add(other.sum);
+        self.min = match (self.min, other.min) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.max = self.max.max(other.max);
+    }
+
+    /// Forget every sample.
+    pub fn reset(&mut self) {
+        self.counts.fill(0);
+        self.count = 0;
+        self.sum = Duration::ZERO;
+        self.min = None;
+        self.max = None;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.min
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.max
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(Duration::from_nanos(
+            (self.sum.as_nanos() / u128::from(self.count)) as u64,
+        ))
+    }
+
+    /// The round trip time that `percentile` percent of the samples do not
+    /// exceed, e.g. `percentile(99.9)`. Returns `None` if the histogram is
+    /// empty.
+    ///
+    /// The result is the upper end of the bucket the sample falls in, but
+    /// never outside the smallest and largest sample recorded.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        let (min, max) = (self.min?, self.max?);
+        let rank = ((percentile.clamp(0.0, 100.0) / 100.0 * self.count as f64).ceil() as u64)
+            .clamp(1, self.count);
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let (_, high) = bucket_range(index);
+                let value = Duration::from_micros(high - 1);
+                return Some(value.clamp(min, max));
+            }
+        }
+        Some(max)
+    }
+
+    /// The non-empty buckets as `(low, high, count)`, where `count` samples
+    /// took at least `low` and less than `high`.
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, Duration, u64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(index, count)| {
+                let (low, high) = bucket_range(index);
+                (
+                    Duration::from_micros(low),
+                    Duration::from_micros(high),
+                    *count,
+                )
+            })
+    }
+}
+
+/// A shared [`RttHistogram`] that pingers record every echo reply into, see
+/// [`Pinger::histogram`](crate::Pinger::histogram) and
+/// [`Client::histogram`](crate::Client::histogram).
+///
+/// Clones refer to the same histogram.
+#[derive(Debug, Clone, Default)]
+pub struct RttRecorder {
+    inner: Arc<Mutex<RttHistogram>>,
+}
+
+impl RttRecorder {
+    pub fn new() -> RttRecorder {
+        RttRecorder::default()
+    }
+
+    pub fn record(&self, rtt: Duration) {
+        self.inner.lock().record(rtt);
+    }
+
+    /// A copy of the histogram as it is now.
+    pub fn snapshot(&self) -> RttHistogram {
+        self.inner.lock().clone()
+    }
+
+    /// Take the histogram and leave an empty one in its place, so no sample
+    /// is lost or counted twice between two reports.
+    pub fn take(&self) -> RttHistogram {
+        let mut snapshot = RttHistogram::new();
+        std::mem::swap(&mut snapshot, &mut *self.inner.lock());
+        snapshot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_are_contiguous() {
+        let mut expected_low = 0;
+        for index in 0..BUCKETS {
+            let (low, high) = bucket_range(index);
+            assert_eq!(low, expected_low);
+            assert_eq!(bucket(low), index);
+            assert_eq!(bucket(high - 1), index);
+            expected_low = high;
+        }
+        assert_eq!(bucket(u64::MAX), BUCKETS - 1);
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut histogram = RttHistogram::new();
+        assert_eq!(histogram.percentile(50.0), None);
+        for ms in 1..=1000 {
+            histogram.record(Duration::from_millis(ms));
+        }
+        assert_eq!(histogram.count(), 1000);
+        assert_eq!(histogram.mean(), Some(Duration::from_micros(500_500)));
+        for (percentile, ms) in [(0.0, 1.0), (50.0, 500.0), (90.0, 900.0), (99.9, 999.0)] {
+            let value = histogram.percentile(percentile).unwrap().as_secs_f64() * 1000.0;
+            assert!(value >= ms && value <= ms * 1.04, "p{percentile} = {value}");
+        }
+        assert_eq!(histogram.percentile(100.0), Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn merge_and_take() {
+        let recorder = RttRecorder::new();
+        recorder.record(Duration::from_micros(10));
+        let mut other = RttHistogram::new();
+        other.record(Duration::from_micros(20));
+        other.record(Duration::from_micros(30));
+
+        let mut merged = recorder.take();
+        assert_eq!(recorder.snapshot().count(), 0);
+        merged.merge(&other);
+        assert_eq!(merged.count(), 3);
+        assert_eq!(merged.min(), Some(Duration::from_micros(10)));
+        assert_eq!(merged.max(), Some(Duration::from_micros(30)));
+        assert_eq!(merged.percentile(50.0), Some(Duration::from_micros(20)));
+        assert_eq!(
+            merged.buckets().collect::<Vec<_>>(),
+            [10, 20, 30].map(|us| (Duration::from_micros(us), Duration::from_micros(us + 1), 1))
+        );
+    }
+}
@@ -3,6 +3,8 @@ use std::convert::TryInto;
 use std::net::Ipv4Addr;
 
 use pnet_packet::icmp::{self, IcmpCode, IcmpType};
+use pnet_packet::ip::IpNextHeaderProtocols;
+use pnet_packet::MutablePacket;
 use pnet_packet::Packet;
 use pnet_packet::{ipv4, PacketSize};
 
@@ -11,26 +13,34 @@ use crate::{
     is_linux_icmp_socket,
 };
 
-use super::{PingIdentifier, PingSequence};
+use super::{message::Icmpv4Message, PayloadStamp, PingIdentifier, PingSequence};
 
 pub fn make_icmpv4_echo_packet(
     ident_hint: PingIdentifier,
     seq_cnt: PingSequence,
     sock_type: SockType,
     payload: &[u8],
+    stamp: Option<PayloadStamp>,
+    flow: Option<u16>,
 ) -> Result<Vec<u8>> {
+    let stamp_len = stamp.map_or(0, |_| PayloadStamp::LEN);
+    let payload = PayloadStamp::prefix(stamp, payload);
     // 8 bytes of header, then payload.
     let mut buf = vec![0; 8 + payload.len()];
     let mut packet = icmp::echo_request::MutableEchoRequestPacket::new(&mut buf[..])
         .ok_or(SurgeError::IncorrectBufferSize)?;
 
     packet.set_icmp_type(icmp::IcmpTypes::EchoRequest);
-    packet.set_payload(payload);
+    packet.set_payload(&payload);
     packet.set_sequence_number(seq_cnt.into_u16());
 
     if !(is_linux_icmp_socket!(sock_type)) {
         packet.set_identifier(ident_hint.into_u16());
-
+    }
+    if let Some(flow) = flow {
+        super::fix_checksum(packet.packet_mut(), 8 + stamp_len, flow)?;
+    }
+    if !(is_linux_icmp_socket!(sock_type)) {
         // Calculate and set the checksum
         let icmp_packet =
             icmp::IcmpPacket::new(packet.packet()).ok_or(SurgeError::IncorrectBufferSize)?;
@@ -42,18 +52,76 @@ pub fn make_icmpv4_echo_packet(
     Ok(packet.packet().to_vec())
 }
 
+fn is_fragmentation_needed(icmp_type: IcmpType, icmp_code: IcmpCode) -> bool {
+    icmp_type == icmp::IcmpTypes::DestinationUnreachable
+        && icmp_code == icmp::destination_unreachable::IcmpCodes::FragmentationRequiredAndDFFlagSet
+}
+
+/// The echo request quoted in the payload of an ICMP error message.
+struct QuotedEcho {
+    tos: u8,
+    destination: Ipv4Addr,
+    identifier: PingIdentifier,
+    sequence: PingSequence,
+}
+
+impl QuotedEcho {
+    /// Decode the quoted packet from the payload of an ICMP error message,
+    /// i.e. everything after the checksum: 4 bytes that depend on the message
+    /// type, then the IPv4 header (with any options) and at least the first
+    /// 8 bytes of the ICMP echo request.
+    fn decode(icmp_payload: &[u8]) -> Result<Self> {
+        let quoted = icmp_payload.get(4..).unwrap_or_default();
+        let ip_packet = ipv4::Ipv4Packet::new(quoted)
+            .ok_or_else(|| SurgeError::from(MalformedPacketError::NotIpv4Packet))?;
+        if ip_packet.get_version() != 4 {
+            return Err(SurgeError::from(MalformedPacketError::NotIpv4Packet));
+        }
+        let header_len = usize::from(ip_packet.get_header_length()) * 4;
+        if header_len < ipv4::Ipv4Packet::minimum_packet_size() {
+            return Err(SurgeError::from(MalformedPacketError::NotIpv4Packet));
+        }
+        if ip_packet.get_next_level_protocol() != IpNextHeaderProtocols::Icmp {
+            return Err(SurgeError::from(MalformedPacketError::QuotedNotEchoRequest));
+        }
+        let want = 4 + header_len + 8;
+        if icmp_payload.len() < want {
+            return Err(SurgeError::from(MalformedPacketError::PayloadTooShort {
+                got: icmp_payload.len(),
+                want,
+            }));
+        }
+        let echo_packet = icmp::echo_request::EchoRequestPacket::new(&quoted[header_len..])
+            .ok_or_else(|| SurgeError::from(MalformedPacketError::NotIcmpv4Packet))?;
+        if echo_packet.get_icmp_type() != icmp::IcmpTypes::EchoRequest {
+            return Err(SurgeError::from(MalformedPacketError::QuotedNotEchoRequest));
+        }
+
+        Ok(QuotedEcho {
+            tos: ip_packet.get_dscp() << 2 | ip_packet.get_ecn(),
+            destination: ip_packet.get_destination(),
+            identifier: echo_packet.get_identifier().into(),
+            sequence: echo_packet.get_sequence_number().into(),
+        })
+    }
+}
+
 /// Packet structure returned by ICMPv4.
 #[derive(Debug)]
 pub struct Icmpv4Packet {
     source: Ipv4Addr,
     destination: Ipv4Addr,
     ttl: Option<u8>,
+    tos: Option<u8>,
+    quoted_tos: Option<u8>,
     icmp_type: IcmpType,
     icmp_code: IcmpCode,
     size: usize,
     real_dest: Ipv4Addr,
     identifier: PingIdentifier,
     sequence: PingSequence,
+    next_hop_mtu: Option<u32>,
+    payload: Vec<u8>,
 }
 
 impl Default for Icmpv4Packet {
@@ -62,12 +130,16 @@ impl Default for Icmpv4Packet {
             source: Ipv4Addr::new(127, 0, 0, 1),
             destination: Ipv4Addr::new(127, 0, 0, 1),
             ttl: None,
+            tos: None,
+            quoted_tos: None,
             icmp_type: IcmpType::new(0),
             icmp_code: IcmpCode::new(0),
             size: 0,
             real_dest: Ipv4Addr::new(127, 0, 0, 1),
             identifier: PingIdentifier(0),
             sequence: PingSequence(0),
+            next_hop_mtu: None,
+            payload: Vec::new(),
         }
     }
 }
@@ -93,16 +165,47 @@ impl Icmpv4Packet {
         self.destination
     }
 
-    fn ttl(&mut self, ttl: u8) -> &mut Self {
+    pub(crate) fn ttl(&mut self, ttl: u8) -> &mut Self {
         self.ttl = Some(ttl);
         self
     }
 
     /// Get the ttl field.
+    ///
+    /// On DGRAM sockets the IP header is not available, so this is only known
+    /// where the kernel reports it (`IP_RECVTTL` on Linux).
     pub fn get_ttl(&self) -> Option<u8> {
         self.ttl
     }
 
+    pub(crate) fn tos(&mut self, tos: u8) -> &mut Self {
+        self.tos = Some(tos);
+        self
+    }
+
+    /// Get the type of service byte (DSCP and ECN) of the received packet.
+    ///
+    /// Like [`Icmpv4Packet::get_ttl`], DGRAM sockets only know it where the
+    /// kernel reports it (`IP_RECVTOS` on Linux).
+    pub fn get_tos(&self) -> Option<u8> {
+        self.tos
+    }
+
+    fn quoted_tos(&mut self, tos: u8) -> &mut Self {
+        self.quoted_tos = Some(tos);
+        self
+    }
+
+    /// Get the type of service byte of the echo request as quoted by an ICMP
+    /// error, i.e. as it reached the router that reported the error.
+    ///
+    /// Comparing it with what was sent shows whether the path remarks it.
+    /// Not available for errors read from the Linux socket error queue,
+    /// which only keeps the ICMP part of the quoted request.
+    pub fn get_quoted_tos(&self) -> Option<u8> {
+        self.quoted_tos
+    }
+
     fn icmp_type(&mut self, icmp_type: IcmpType) -> &mut Self {
         self.icmp_type = icmp_type;
         self
@@ -113,6 +216,11 @@ impl Icmpv4Packet {
         self.icmp_type
     }
 
+    /// Get the type and code of the icmpv4 packet by name.
+    pub fn get_message(&self) -> Icmpv4Message {
+        Icmpv4Message::new(self.icmp_type.0, self.icmp_code.0)
+    }
+
     fn icmp_code(&mut self, icmp_code: IcmpCode) -> &mut Self {
         self.icmp_code = icmp_code;
         self
@@ -164,6 +272,27 @@ impl Icmpv4Packet {
         self.sequence
     }
 
+    fn next_hop_mtu(&mut self, mtu: u32) -> &mut Self {
+        self.next_hop_mtu = Some(mtu);
+        self
+    }
+
+    /// Get the next-hop MTU of a `Fragmentation Needed` message.
+    pub fn get_next_hop_mtu(&self) -> Option<u32> {
+        self.next_hop_mtu
+    }
+
+    fn payload(&mut self, payload: &[u8]) -> &mut Self {
+        self.payload = payload.to_vec();
+        self
+    }
+
+    /// Get the data of an echo reply, i.e. everything after the identifier
+    /// and sequence number. Empty for other messages.
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+
     /// Decode into icmp packet from the socket message.
     pub fn decode(
         buf: &[u8],
@@ -194,39 +323,39 @@ impl Icmpv4Packet {
                     .source(ipv4_packet.get_source())
                     .destination(ipv4_packet.get_destination())
                     .ttl(ipv4_packet.get_ttl())
+                    .tos(ipv4_packet.get_dscp() << 2 | ipv4_packet.get_ecn())
                     .icmp_type(icmp_packet.get_icmp_type())
                     .icmp_code(icmp_packet.get_icmp_code())
                     .size(icmp_packet.packet().len())
                     .real_dest(ipv4_packet.get_source())
                     .identifier(icmp_packet.get_identifier().into())
-                    .sequence(icmp_packet.get_sequence_number().into());
+                    .sequence(icmp_packet.get_sequence_number().into())
+                    .payload(icmp_packet.payload());
             }
             icmp::IcmpTypes::EchoRequest => return Err(SurgeError::EchoRequestPacket),
             _ => {
                 let icmp_payload = icmp_packet.payload();
-
-                if icmp_payload.len() < 32 {
-                    return Err(SurgeError::from(MalformedPacketError::PayloadTooShort {
-                        got: icmp_payload.len(),
-                        want: 32,
-                    }));
-                }
-                // icmp unused(4) + ip header(20) + echo icmp(4)
-                let real_ip_packet = ipv4::Ipv4Packet::new(&icmp_payload[4..])
-                    .ok_or_else(|| SurgeError::from(MalformedPacketError::NotIpv4Packet))?;
-                let identifier = u16::from_be_bytes(icmp_payload[28..30].try_into().unwrap());
-                let sequence = u16::from_be_bytes(icmp_payload[30..32].try_into().unwrap());
+                let quoted = QuotedEcho::decode(icmp_payload)?;
 
                 packet
                     .source(ipv4_packet.get_source())
                     .destination(ipv4_packet.get_destination())
                     .ttl(ipv4_packet.get_ttl())
+                    .tos(ipv4_packet.get_dscp() << 2 | ipv4_packet.get_ecn())
                     .icmp_type(icmp_packet.get_icmp_type())
                     .icmp_code(icmp_packet.get_icmp_code())
                     .size(icmp_packet.packet_size())
-                    .real_dest(real_ip_packet.get_destination())
-                    .identifier(identifier.into())
-                    .sequence(sequence.into());
+                    .real_dest(quoted.destination)
+                    .quoted_tos(quoted.tos)
+                    .identifier(quoted.identifier)
+                    .sequence(quoted.sequence);
+                if is_fragmentation_needed(icmp_packet.get_icmp_type(), icmp_packet.get_icmp_code())
+                {
+                    // icmp unused(2) + next-hop mtu(2)
+                    packet.next_hop_mtu(
+                        u16::from_be_bytes(icmp_payload[2..4].try_into().unwrap()).into(),
+                    );
+                }
             }
         }
 
@@ -251,24 +380,13 @@ impl Icmpv4Packet {
                     .size(icmp_packet.packet().len())
                     .real_dest(src_addr)
                     .identifier(icmp_packet.get_identifier().into())
-                    .sequence(icmp_packet.get_sequence_number().into());
+                    .sequence(icmp_packet.get_sequence_number().into())
+                    .payload(icmp_packet.payload());
             }
             icmp::IcmpTypes::EchoRequest => return Err(SurgeError::EchoRequestPacket),
             _ => {
                 let icmp_payload = icmp_packet.payload();
-
-                if icmp_payload.len() < 32 {
-                    return Err(SurgeError::from(MalformedPacketError::PayloadTooShort {
-                        got: icmp_payload.len(),
-                        want: 32,
-                    }));
-                }
-
-                // icmp unused(4) + ip header(20) + echo icmp(4)
-                let real_ip_packet = ipv4::Ipv4Packet::new(&icmp_payload[4..])
-                    .ok_or_else(|| SurgeError::from(MalformedPacketError::NotIpv4Packet))?;
-                let identifier = u16::from_be_bytes(icmp_payload[28..30].try_into().unwrap());
-                let sequence = u16::from_be_bytes(icmp_payload[30..32].try_into().unwrap());
+                let quoted = QuotedEcho::decode(icmp_payload)?;
 
                 packet
                     .source(src_addr)
@@ -276,14 +394,59 @@ impl Icmpv4Packet {
                     .icmp_type(icmp_packet.get_icmp_type())
                     .icmp_code(icmp_packet.get_icmp_code())
                     .size(icmp_packet.packet_size())
-                    .real_dest(real_ip_packet.get_destination())
-                    .identifier(identifier.into())
-                    .sequence(sequence.into());
+                    .real_dest(quoted.destination)
+                    .quoted_tos(quoted.tos)
+                    .identifier(quoted.identifier)
+                    .sequence(quoted.sequence);
+                if is_fragmentation_needed(icmp_packet.get_icmp_type(), icmp_packet.get_icmp_code())
+                {
+                    // icmp unused(2) + next-hop mtu(2)
+                    packet.next_hop_mtu(
+                        u16::from_be_bytes(icmp_payload[2..4].try_into().unwrap()).into(),
+                    );
+                }
             }
         }
 
         Ok(packet)
     }
+
+    /// Decode an ICMP error reported through the Linux socket error queue.
+    ///
+    /// `buf` is the echo request the error refers to, `info` the kernel's
+    /// `ee_info` (the next-hop MTU for `Fragmentation Needed`), `src_addr` the
+    /// host that reported the error and `real_dest` the host the request was
+    /// sent to.
+    #[cfg_attr(not(any(target_os = "linux", target_os = "android")), allow(dead_code))]
+    pub(crate) fn decode_from_error_queue(
+        buf: &[u8],
+        icmp_type: u8,
+        icmp_code: u8,
+        info: u32,
+        src_addr: Ipv4Addr,
+        dst_addr: Ipv4Addr,
+        real_dest: Ipv4Addr,
+    ) -> Result<Self> {
+        let echo_packet = icmp::echo_request::EchoRequestPacket::new(buf)
+            .ok_or_else(|| SurgeError::from(MalformedPacketError::NotIcmpv4Packet))?;
+        if echo_packet.get_icmp_type() != icmp::IcmpTypes::EchoRequest {
+            return Err(SurgeError::from(MalformedPacketError::NotIcmpv4Packet));
+        }
+        let mut packet = Icmpv4Packet::default();
+        packet
+            .source(src_addr)
+            .destination(dst_addr)
+            .icmp_type(IcmpType::new(icmp_type))
+            .icmp_code(IcmpCode::new(icmp_code))
+            .size(buf.len())
+            .real_dest(real_dest)
+            .identifier(echo_packet.get_identifier().into())
+            .sequence(echo_packet.get_sequence_number().into());
+        if is_fragmentation_needed(IcmpType::new(icmp_type), IcmpCode::new(icmp_code)) {
+            packet.next_hop_mtu(info);
+        }
+        Ok(packet)
+    }
 }
 
 #[cfg(test)]
@@ -291,6 +454,26 @@ mod tests {
     use super::*;
     use crate::Icmpv4Packet;
 
+    #[test]
+    fn paris_checksum() {
+        let stamp = PayloadStamp::now(7);
+        for seq in 0..4 {
+            let packet = make_icmpv4_echo_packet(
+                PingIdentifier(0x1234),
+                PingSequence(seq),
+                SockType::RAW,
+                &[0xab; 20],
+                Some(stamp),
+                Some(0xbeef),
+            )
+            .unwrap();
+            assert_eq!(&packet[2..4], &[0xbe, 0xef]);
+            assert_eq!(&packet[4..6], &[0x12, 0x34]);
+            assert_eq!(PayloadStamp::decode(&packet[8..]), Some(stamp));
+            assert_eq!(&packet[22..], &[0xab; 18]);
+        }
+    }
+
     #[test]
     fn malformed_packet() {
         let decoded_ipv4 =
@@ -337,22 +520,187 @@ mod tests {
 
     #[test]
     fn standard_packet() {
-        let decoded_ipv4 = hex::decode("45000054000000007901067e8efab00e0a00f22203004176a1ee0001613dd762000000002127040000000000101112131415161718191a1b1c1d1e1f202122232425262728292a2b2c2d2e2f3031323334353637").unwrap();
-        Icmpv4Packet::decode(
+        let decoded_ipv4 = hex::decode("45000054000000007901067e8efab00e0a00f22200004176a1ee0001613dd762000000002127040000000000101112131415161718191a1b1c1d1e1f202122232425262728292a2b2c2d2e2f3031323334353637").unwrap();
+        let packet = Icmpv4Packet::decode(
             &decoded_ipv4,
             SockType::RAW,
             ("172.217.14.110").parse().unwrap(),
             ("10.0.242.34").parse().unwrap(),
         )
         .unwrap();
+        assert_eq!(packet.get_ttl(), Some(121));
+        assert_eq!(packet.get_tos(), Some(0));
 
+        // The IP header is cropped off on DGRAM sockets; the TTL and TOS
+        // arrive as control messages instead.
+        let decoded_icmp = hex::decode("00004176a1ee0001613dd762000000002127040000000000101112131415161718191a1b1c1d1e1f202122232425262728292a2b2c2d2e2f3031323334353637").unwrap();
+        let packet = Icmpv4Packet::decode(
+            &decoded_icmp,
+            SockType::DGRAM,
+            ("172.217.14.110").parse().unwrap(),
+            ("10.0.242.34").parse().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(packet.get_ttl(), None);
+        assert_eq!(packet.get_tos(), None);
+
+        // The same bytes as a destination unreachable do not quote an IPv4 header.
         let decoded_icmp = hex::decode("03004176a1ee0001613dd762000000002127040000000000101112131415161718191a1b1c1d1e1f202122232425262728292a2b2c2d2e2f3031323334353637").unwrap();
-        Icmpv4Packet::decode(
+        assert!(Icmpv4Packet::decode(
             &decoded_icmp,
             SockType::DGRAM,
             ("172.217.14.110").parse().unwrap(),
             ("10.0.242.34").parse().unwrap(),
         )
+        .is_err());
+    }
+
+    #[test]
+    fn quoted_header_with_options() {
+        let decoded_icmp = hex::decode(
+            "0b000000000000004600002000000000400100000a00000208080808010100000800000012340005",
+        )
+        .unwrap();
+        let packet = Icmpv4Packet::decode(
+            &decoded_icmp,
+            SockType::DGRAM,
+            ("10.0.0.1").parse().unwrap(),
+            ("10.0.0.2").parse().unwrap(),
+        )
         .unwrap();
+        assert_eq!(packet.get_real_dest(), Ipv4Addr::new(8, 8, 8, 8));
+        assert_eq!(packet.get_identifier(), PingIdentifier(0x1234));
+        assert_eq!(packet.get_sequence(), PingSequence(5));
+    }
+
+    #[test]
+    fn quoted_header_not_echo_request() {
+        let decoded_icmp =
+            hex::decode("0b000000000000004500001c00000000401100000a000002080808080035003500080000")
+                .unwrap();
+        let err = Icmpv4Packet::decode(
+            &decoded_icmp,
+            SockType::DGRAM,
+            ("10.0.0.1").parse().unwrap(),
+            ("10.0.0.2").parse().unwrap(),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            SurgeError::MalformedPacket(MalformedPacketError::QuotedNotEchoRequest)
+        ));
+    }
+
+    #[test]
+    fn time_exceeded_packet() {
+        let decoded_ipv4 = hex::decode("4500003800000000400100000a0000010a0000020b000000000000004500001c00000000400100000a000002080808080800000012340005").unwrap();
+        let packet = Icmpv4Packet::decode(
+            &decoded_ipv4,
+            SockType::RAW,
+            ("10.0.0.1").parse().unwrap(),
+            ("10.0.0.2").parse().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(packet.get_source(), Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(packet.get_real_dest(), Ipv4Addr::new(8, 8, 8, 8));
+        assert_eq!(packet.get_identifier(), PingIdentifier(0x1234));
+        assert_eq!(packet.get_sequence(), PingSequence(5));
+
+        let decoded_icmp =
+            hex::decode("0b000000000000004500001c00000000400100000a000002080808080800000012340005")
+                .unwrap();
+        let packet = crate::IcmpPacket::V4(
+            Icmpv4Packet::decode(
+                &decoded_icmp,
+                SockType::DGRAM,
+                ("10.0.0.1").parse().unwrap(),
+                ("10.0.0.2").parse().unwrap(),
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            packet.get_real_dest(),
+            "8.8.8.8".parse::<std::net::IpAddr>().unwrap()
+        );
+        assert!(packet.is_from_intermediate_hop());
+        assert_eq!(
+            packet.outcome(),
+            crate::PingOutcome::TimeExceeded {
+                from: "10.0.0.1".parse().unwrap()
+            }
+        );
+    }
+
+    #[test]
+    fn fragmentation_needed_packet() {
+        let decoded_icmp =
+            hex::decode("03040000000005784500001c00004000400100000a000002080808080800000012340005")
+                .unwrap();
+        let packet = Icmpv4Packet::decode(
+            &decoded_icmp,
+            SockType::DGRAM,
+            ("10.0.0.1").parse().unwrap(),
+            ("10.0.0.2").parse().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(packet.get_next_hop_mtu(), Some(1400));
+        assert_eq!(
+            crate::IcmpPacket::V4(packet).outcome(),
+            crate::PingOutcome::PacketTooBig {
+                mtu: 1400,
+                from: "10.0.0.1".parse().unwrap()
+            }
+        );
+    }
+
+    #[test]
+    fn quoted_tos() {
+        // Time Exceeded quoting a request sent with DSCP EF.
+        let decoded_icmp =
+            hex::decode("0b0000000000000045b8001c00004000400100000a000002080808080800000012340005")
+                .unwrap();
+        let packet = Icmpv4Packet::decode(
+            &decoded_icmp,
+            SockType::DGRAM,
+            ("10.0.0.1").parse().unwrap(),
+            ("10.0.0.2").parse().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(packet.get_quoted_tos(), Some(0xb8));
+        assert_eq!(packet.get_tos(), None);
+    }
+
+    #[test]
+    fn error_queue_packet() {
+        let echo_request = hex::decode("0800000012340005").unwrap();
+        let packet = Icmpv4Packet::decode_from_error_queue(
+            &echo_request,
+            3,
+            1,
+            0,
+            ("10.0.0.1").parse().unwrap(),
+            ("10.0.0.2").parse().unwrap(),
+            ("8.8.8.8").parse().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            packet.get_icmp_type(),
+            icmp::IcmpTypes::DestinationUnreachable
+        );
+        assert_eq!(packet.get_source(), Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(packet.get_real_dest(), Ipv4Addr::new(8, 8, 8, 8));
+        assert_eq!(packet.get_sequence(), PingSequence(5));
+
+        let echo_reply = hex::decode("0000000012340005").unwrap();
+        assert!(Icmpv4Packet::decode_from_error_queue(
+            &echo_reply,
+            3,
+            1,
+            0,
+            ("10.0.0.1").parse().unwrap(),
+            ("10.0.0.2").parse().unwrap(),
+            ("8.8.8.8").parse().unwrap(),
+        )
+        .is_err());
     }
 }
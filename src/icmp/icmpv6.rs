@@ -2,45 +2,136 @@ use std::convert::TryInto;
 use std::net::Ipv6Addr;
 
 use pnet_packet::icmpv6::{self, Icmpv6Code, Icmpv6Type};
+use pnet_packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use pnet_packet::ipv6;
+use pnet_packet::MutablePacket;
 use pnet_packet::Packet;
 use pnet_packet::PacketSize;
 
 use crate::error::{MalformedPacketError, Result, SurgeError};
 
-use super::{PingIdentifier, PingSequence};
+use super::{message::Icmpv6Message, PayloadStamp, PingIdentifier, PingSequence};
 
 #[allow(dead_code)]
 pub fn make_icmpv6_echo_packet(
     ident: PingIdentifier,
     seq_cnt: PingSequence,
     payload: &[u8],
+    stamp: Option<PayloadStamp>,
+    flow: Option<u16>,
 ) -> Result<Vec<u8>> {
+    let stamp_len = stamp.map_or(0, |_| PayloadStamp::LEN);
+    let payload = PayloadStamp::prefix(stamp, payload);
     let mut buf = vec![0; 8 + payload.len()]; // 8 bytes of header, then payload
     let mut packet = icmpv6::echo_request::MutableEchoRequestPacket::new(&mut buf[..])
         .ok_or(SurgeError::IncorrectBufferSize)?;
     packet.set_icmpv6_type(icmpv6::Icmpv6Types::EchoRequest);
     packet.set_identifier(ident.into_u16());
     packet.set_sequence_number(seq_cnt.into_u16());
-    packet.set_payload(payload);
+    packet.set_payload(&payload);
 
     // Per https://tools.ietf.org/html/rfc3542#section-3.1 the checksum is
     // omitted, the kernel will insert it.
+    if let Some(flow) = flow {
+        super::fix_checksum(packet.packet_mut(), 8 + stamp_len, flow)?;
+        packet.set_checksum(0);
+    }
 
     Ok(packet.packet().to_vec())
 }
 
+/// The echo request quoted in the payload of an ICMPv6 error message.
+struct QuotedEcho {
+    traffic_class: u8,
+    source: Ipv6Addr,
+    destination: Ipv6Addr,
+    identifier: PingIdentifier,
+    sequence: PingSequence,
+}
+
+impl QuotedEcho {
+    /// Decode the quoted packet from the payload of an ICMPv6 error message,
+    /// i.e. everything after the checksum: 4 bytes that depend on the message
+    /// type, then the IPv6 header, any extension headers and at least the
+    /// first 8 bytes of the ICMPv6 echo request.
+    fn decode(icmpv6_payload: &[u8]) -> Result<Self> {
+        let quoted = icmpv6_payload.get(4..).unwrap_or_default();
+        let ip_packet = ipv6::Ipv6Packet::new(quoted)
+            .ok_or_else(|| SurgeError::from(MalformedPacketError::NotIpv6Packet))?;
+        if ip_packet.get_version() != 6 {
+            return Err(SurgeError::from(MalformedPacketError::NotIpv6Packet));
+        }
+
+        // Walk the extension header chain up to the ICMPv6 header.
+        let mut next_header = ip_packet.get_next_header();
+        let mut offset = ipv6::Ipv6Packet::minimum_packet_size();
+        while next_header != IpNextHeaderProtocols::Icmpv6 {
+            let header = quoted.get(offset..offset + 2).ok_or_else(|| {
+                SurgeError::from(MalformedPacketError::PayloadTooShort {
+                    got: icmpv6_payload.len(),
+                    want: 4 + offset + 2,
+                })
+            })?;
+            let header_len = match next_header {
+                IpNextHeaderProtocols::Hopopt
+                | IpNextHeaderProtocols::Ipv6Route
+                | IpNextHeaderProtocols::Ipv6Opts => (usize::from(header[1]) + 1) * 8,
+                IpNextHeaderProtocols::Ipv6Frag => {
+                    // Only the first fragment carries the ICMPv6 header.
+                    let fragment_offset = quoted
+                        .get(offset + 2..offset + 4)
+                        .map(|b| u16::from_be_bytes([b[0], b[1]]) >> 3);
+                    if fragment_offset != Some(0) {
+                        return Err(SurgeError::from(MalformedPacketError::QuotedNotEchoRequest));
+                    }
+                    8
+                }
+                IpNextHeaderProtocols::Ah => (usize::from(header[1]) + 2) * 4,
+                _ => return Err(SurgeError::from(MalformedPacketError::QuotedNotEchoRequest)),
+            };
+            next_header = IpNextHeaderProtocol::new(header[0]);
+            offset += header_len;
+        }
+
+        let want = 4 + offset + 8;
+        if icmpv6_payload.len() < want {
+            return Err(SurgeError::from(MalformedPacketError::PayloadTooShort {
+                got: icmpv6_payload.len(),
+                want,
+            }));
+        }
+        let echo_packet = icmpv6::echo_request::EchoRequestPacket::new(&quoted[offset..])
+            .ok_or_else(|| SurgeError::from(MalformedPacketError::NotIcmpv6Packet))?;
+        if echo_packet.get_icmpv6_type() != icmpv6::Icmpv6Types::EchoRequest {
+            return Err(SurgeError::from(MalformedPacketError::QuotedNotEchoRequest));
+        }
+
+        Ok(QuotedEcho {
+            traffic_class: ip_packet.get_traffic_class(),
+            source: ip_packet.get_source(),
+            destination: ip_packet.get_destination(),
+            identifier: echo_packet.get_identifier().into(),
+            sequence: echo_packet.get_sequence_number().into(),
+        })
+    }
+}
+
 /// Packet structure returned by ICMPv6.
 #[derive(Debug)]
 pub struct Icmpv6Packet {
     source: Ipv6Addr,
     destination: Ipv6Addr,
     max_hop_limit: u8,
+    traffic_class: Option<u8>,
+    quoted_traffic_class: Option<u8>,
     icmpv6_type: Icmpv6Type,
     icmpv6_code: Icmpv6Code,
     size: usize,
     real_dest: Ipv6Addr,
     identifier: PingIdentifier,
     sequence: PingSequence,
+    mtu: Option<u32>,
+    payload: Vec<u8>,
 }
 
 impl Default for Icmpv6Packet {
@@ -49,12 +140,16 @@ impl Default for Icmpv6Packet {
             source: Ipv6Addr::LOCALHOST,
             destination: Ipv6Addr::LOCALHOST,
             max_hop_limit: 0,
+            traffic_class: None,
+            quoted_traffic_class: None,
             icmpv6_type: Icmpv6Type::new(0),
             icmpv6_code: Icmpv6Code::new(0),
             size: 0,
             real_dest: Ipv6Addr::LOCALHOST,
             identifier: PingIdentifier(0),
             sequence: PingSequence(0),
+            mtu: None,
+            payload: Vec::new(),
         }
     }
 }
@@ -65,7 +160,8 @@ impl Icmpv6Packet {
         self
     }
 
-    /// Get the source IPv6 address.
+    /// Get the source IPv6 address, i.e. the host that sent this packet. For
+    /// error messages this is the router or host reporting the error.
     pub fn get_source(&self) -> Ipv6Addr {
         self.source
     }
@@ -75,21 +171,53 @@ impl Icmpv6Packet {
         self
     }
 
-    /// Get the destination IPv6 address.
+    /// Get the destination IPv6 address. For error messages this is the
+    /// source address quoted from the original echo request.
     pub fn get_destination(&self) -> Ipv6Addr {
         self.destination
     }
 
-    fn max_hop_limit(&mut self, max_hop_limit: u8) -> &mut Self {
+    pub(crate) fn max_hop_limit(&mut self, max_hop_limit: u8) -> &mut Self {
         self.max_hop_limit = max_hop_limit;
         self
     }
 
-    /// Get the hop_limit field.
+    /// Get the hop limit of the received packet.
+    ///
+    /// The IPv6 header is not available to the socket, so this is only known
+    /// where the kernel reports it (`IPV6_RECVHOPLIMIT` on Linux) and is 0
+    /// otherwise.
     pub fn get_max_hop_limit(&self) -> u8 {
         self.max_hop_limit
     }
 
+    pub(crate) fn traffic_class(&mut self, traffic_class: u8) -> &mut Self {
+        self.traffic_class = Some(traffic_class);
+        self
+    }
+
+    /// Get the traffic class (DSCP and ECN) of the received packet.
+    ///
+    /// Like [`Icmpv6Packet::get_max_hop_limit`], this is only known where the
+    /// kernel reports it (`IPV6_RECVTCLASS` on Linux).
+    pub fn get_traffic_class(&self) -> Option<u8> {
+        self.traffic_class
+    }
+
+    fn quoted_traffic_class(&mut self, traffic_class: u8) -> &mut Self {
+        self.quoted_traffic_class = Some(traffic_class);
+        self
+    }
+
+    /// Get the traffic class of the echo request as quoted by an ICMPv6
+    /// error, i.e. as it reached the router that reported the error.
+    ///
+    /// Not available for errors read from the Linux socket error queue,
+    /// which only keeps the ICMPv6 part of the quoted request.
+    pub fn get_quoted_traffic_class(&self) -> Option<u8> {
+        self.quoted_traffic_class
+    }
+
     fn icmpv6_type(&mut self, icmpv6_type: Icmpv6Type) -> &mut Self {
         self.icmpv6_type = icmpv6_type;
         self
@@ -100,6 +228,11 @@ impl Icmpv6Packet {
         self.icmpv6_type
     }
 
+    /// Get the type and code of the icmpv6 packet by name.
+    pub fn get_message(&self) -> Icmpv6Message {
+        Icmpv6Message::new(self.icmpv6_type.0, self.icmpv6_code.0)
+    }
+
     fn icmpv6_code(&mut self, icmpv6_code: Icmpv6Code) -> &mut Self {
         self.icmpv6_code = icmpv6_code;
         self
@@ -151,6 +284,27 @@ impl Icmpv6Packet {
         self.sequence
     }
 
+    fn mtu(&mut self, mtu: u32) -> &mut Self {
+        self.mtu = Some(mtu);
+        self
+    }
+
+    /// Get the MTU of a `Packet Too Big` message.
+    pub fn get_mtu(&self) -> Option<u32> {
+        self.mtu
+    }
+
+    fn payload(&mut self, payload: &[u8]) -> &mut Self {
+        self.payload = payload.to_vec();
+        self
+    }
+
+    /// Get the data of an echo reply, i.e. everything after the identifier
+    /// and sequence number. Empty for other messages.
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+
     /// Decode into icmpv6 packet from the socket message.
     pub fn decode(buf: &[u8], destination: Ipv6Addr) -> Result<Self> {
         // The IPv6 header is automatically cropped off when recvfrom() is used.
@@ -178,31 +332,140 @@ impl Icmpv6Packet {
                     .size(icmpv6_packet.packet().len())
                     .real_dest(destination)
                     .identifier(identifier.into())
-                    .sequence(sequence.into());
+                    .sequence(sequence.into())
+                    .payload(&icmpv6_payload[4..]);
                 Ok(packet)
             }
             _ => {
-                // ipv6 header(40) + icmpv6 echo header(4)
-                if icmpv6_payload.len() < 48 {
-                    return Err(SurgeError::from(MalformedPacketError::PayloadTooShort {
-                        got: icmpv6_payload.len(),
-                        want: 48,
-                    }));
-                }
-                let identifier = u16::from_be_bytes(icmpv6_payload[44..46].try_into().unwrap());
-                let sequence = u16::from_be_bytes(icmpv6_payload[46..48].try_into().unwrap());
+                let quoted = QuotedEcho::decode(icmpv6_payload)?;
                 let mut packet = Icmpv6Packet::default();
                 packet
                     .source(destination)
-                    .destination(destination)
+                    .destination(quoted.source)
                     .max_hop_limit(0)
                     .icmpv6_type(icmpv6_packet.get_icmpv6_type())
                     .icmpv6_code(icmpv6_packet.get_icmpv6_code())
                     .size(icmpv6_packet.packet_size())
-                    .identifier(identifier.into())
-                    .sequence(sequence.into());
+                    .real_dest(quoted.destination)
+                    .quoted_traffic_class(quoted.traffic_class)
+                    .identifier(quoted.identifier)
+                    .sequence(quoted.sequence);
+                if icmpv6_packet.get_icmpv6_type() == icmpv6::Icmpv6Types::PacketTooBig {
+                    packet.mtu(u32::from_be_bytes(icmpv6_payload[0..4].try_into().unwrap()));
+                }
                 Ok(packet)
             }
         }
     }
+
+    /// Decode an ICMPv6 error reported through the Linux socket error queue.
+    ///
+    /// `buf` is the echo request the error refers to, `info` the kernel's
+    /// `ee_info` (the MTU for `Packet Too Big`), `source` the host that
+    /// reported the error, `destination` our own address the request was sent
+    /// from and `real_dest` the host the request was sent to.
+    #[cfg_attr(not(any(target_os = "linux", target_os = "android")), allow(dead_code))]
+    pub(crate) fn decode_from_error_queue(
+        buf: &[u8],
+        icmpv6_type: u8,
+        icmpv6_code: u8,
+        info: u32,
+        source: Ipv6Addr,
+        destination: Ipv6Addr,
+        real_dest: Ipv6Addr,
+    ) -> Result<Self> {
+        let echo_packet = icmpv6::echo_request::EchoRequestPacket::new(buf)
+            .ok_or_else(|| SurgeError::from(MalformedPacketError::NotIcmpv6Packet))?;
+        if echo_packet.get_icmpv6_type() != icmpv6::Icmpv6Types::EchoRequest {
+            return Err(SurgeError::from(MalformedPacketError::NotIcmpv6Packet));
+        }
+        let mut packet = Icmpv6Packet::default();
+        packet
+            .source(source)
+            .destination(destination)
+            .max_hop_limit(0)
+            .icmpv6_type(Icmpv6Type::new(icmpv6_type))
+            .icmpv6_code(Icmpv6Code::new(icmpv6_code))
+            .size(buf.len())
+            .real_dest(real_dest)
+            .identifier(echo_packet.get_identifier().into())
+            .sequence(echo_packet.get_sequence_number().into());
+        if packet.icmpv6_type == icmpv6::Icmpv6Types::PacketTooBig {
+            packet.mtu(info);
+        }
+        Ok(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn destination_unreachable_packet() {
+        let decoded = hex::decode("01030000000000006000000000083a4020010db800000000000000000000000220010db80000000000000000000000088000000012340005").unwrap();
+        let packet = Icmpv6Packet::decode(&decoded, "2001:db8::1".parse().unwrap()).unwrap();
+        assert_eq!(
+            packet.get_source(),
+            "2001:db8::1".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(
+            packet.get_destination(),
+            "2001:db8::2".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(
+            packet.get_real_dest(),
+            "2001:db8::8".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(packet.get_identifier(), PingIdentifier(0x1234));
+        assert_eq!(packet.get_sequence(), PingSequence(5));
+        assert!(crate::IcmpPacket::V6(packet).is_from_intermediate_hop());
+    }
+
+    #[test]
+    fn quoted_traffic_class() {
+        let decoded = hex::decode("030000000000000068a0000000083a4020010db800000000000000000000000220010db80000000000000000000000088000000012340005").unwrap();
+        let packet = Icmpv6Packet::decode(&decoded, "2001:db8::1".parse().unwrap()).unwrap();
+        assert_eq!(packet.get_quoted_traffic_class(), Some(0x8a));
+        assert_eq!(packet.get_traffic_class(), None);
+    }
+
+    #[test]
+    fn packet_too_big_packet() {
+        let decoded = hex::decode("02000000000005006000000000083a4020010db800000000000000000000000220010db80000000000000000000000088000000012340005").unwrap();
+        let packet = Icmpv6Packet::decode(&decoded, "2001:db8::1".parse().unwrap()).unwrap();
+        assert_eq!(packet.get_mtu(), Some(1280));
+        assert_eq!(
+            crate::IcmpPacket::V6(packet).outcome(),
+            crate::PingOutcome::PacketTooBig {
+                mtu: 1280,
+                from: "2001:db8::1".parse().unwrap()
+            }
+        );
+    }
+
+    #[test]
+    fn quoted_extension_headers() {
+        let decoded = hex::decode("0300000000000000600000000010004020010db800000000000000000000000220010db80000000000000000000000083a000104000000008000000012340005").unwrap();
+        let packet = Icmpv6Packet::decode(&decoded, "2001:db8::1".parse().unwrap()).unwrap();
+        assert_eq!(
+            packet.get_real_dest(),
+            "2001:db8::8".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(packet.get_identifier(), PingIdentifier(0x1234));
+        assert_eq!(packet.get_sequence(), PingSequence(5));
+
+        // Truncated inside the hop-by-hop options header.
+        assert!(Icmpv6Packet::decode(&decoded[..50], "2001:db8::1".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn quoted_header_not_echo_request() {
+        let decoded = hex::decode("0300000000000000600000000008114020010db800000000000000000000000220010db80000000000000000000000080035003500080000").unwrap();
+        let err = Icmpv6Packet::decode(&decoded, "2001:db8::1".parse().unwrap()).unwrap_err();
+        assert!(matches!(
+            err,
+            SurgeError::MalformedPacket(MalformedPacketError::QuotedNotEchoRequest)
+        ));
+    }
 }
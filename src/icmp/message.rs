@@ -0,0 +1,414 @@
+//! Crate-owned names for the ICMPv4 (RFC 792) and ICMPv6 (RFC 4443) message
+//! types and codes, with `Display` strings following iputils' `ping`.
+//!
+//! Every enum converts to and from its raw `u8` value. Values this crate has
+//! no name for are kept in an `Other` variant, so `u8 -> enum -> u8` always
+//! gives back the original value.
+use std::fmt;
+
+macro_rules! icmp_enum {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $( $(#[$vmeta:meta])* $variant:ident = $value:literal => $text:literal, )*
+        }
+        other => $other:literal
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $( $(#[$vmeta])* $variant, )*
+            /// A value this crate has no name for.
+            Other(u8),
+        }
+
+        impl From<u8> for $name {
+            fn from(value: u8) -> Self {
+                match value {
+                    $( $value => $name::$variant, )*
+                    other => $name::Other(other),
+                }
+            }
+        }
+
+        impl From<$name> for u8 {
+            fn from(value: $name) -> u8 {
+                match value {
+                    $( $name::$variant => $value, )*
+                    $name::Other(other) => other,
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                match self {
+                    $( $name::$variant => f.write_str($text), )*
+                    $name::Other(other) => write!(f, $other, other),
+                }
+            }
+        }
+    };
+}
+
+icmp_enum! {
+    /// ICMPv4 message types.
+    pub enum Icmpv4Type {
+        EchoReply = 0 => "Echo Reply",
+        DestinationUnreachable = 3 => "Destination Unreachable",
+        SourceQuench = 4 => "Source Quench",
+        Redirect = 5 => "Redirect",
+        EchoRequest = 8 => "Echo Request",
+        RouterAdvertisement = 9 => "Router Advertisement",
+        RouterSolicitation = 10 => "Router Solicitation",
+        TimeExceeded = 11 => "Time Exceeded",
+        ParameterProblem = 12 => "Parameter Problem",
+        Timestamp = 13 => "Timestamp",
+        TimestampReply = 14 => "Timestamp Reply",
+        InformationRequest = 15 => "Information Request",
+        InformationReply = 16 => "Information Reply",
+        AddressMaskRequest = 17 => "Address Mask Request",
+        AddressMaskReply = 18 => "Address Mask Reply",
+        Traceroute = 30 => "Traceroute",
+    }
+    other => "Bad ICMP type: {}"
+}
+
+icmp_enum! {
+    /// Codes of an ICMPv4 `Destination Unreachable` message.
+    pub enum Icmpv4UnreachableCode {
+        NetUnreachable = 0 => "Destination Net Unreachable",
+        HostUnreachable = 1 => "Destination Host Unreachable",
+        ProtocolUnreachable = 2 => "Destination Protocol Unreachable",
+        PortUnreachable = 3 => "Destination Port Unreachable",
+        FragmentationNeeded = 4 => "Frag needed and DF set",
+        SourceRouteFailed = 5 => "Source Route Failed",
+        NetUnknown = 6 => "Destination Net Unknown",
+        HostUnknown = 7 => "Destination Host Unknown",
+        SourceHostIsolated = 8 => "Source Host Isolated",
+        NetProhibited = 9 => "Destination Net Prohibited",
+        HostProhibited = 10 => "Destination Host Prohibited",
+        NetUnreachableForTos = 11 => "Destination Net Unreachable for Type of Service",
+        HostUnreachableForTos = 12 => "Destination Host Unreachable for Type of Service",
+        AdminProhibited = 13 => "Packet filtered",
+        HostPrecedenceViolation = 14 => "Precedence Violation",
+        PrecedenceCutoff = 15 => "Precedence Cutoff",
+    }
+    other => "Dest Unreachable, Bad Code: {}"
+}
+
+icmp_enum! {
+    /// Codes of an ICMPv4 `Redirect` message.
+    pub enum Icmpv4RedirectCode {
+        Network = 0 => "Redirect Network",
+        Host = 1 => "Redirect Host",
+        TosNetwork = 2 => "Redirect Type of Service and Network",
+        TosHost = 3 => "Redirect Type of Service and Host",
+    }
+    other => "Redirect, Bad Code: {}"
+}
+
+icmp_enum! {
+    /// Codes of an ICMPv4 `Time Exceeded` message.
+    pub enum Icmpv4TimeExceededCode {
+        TtlExceeded = 0 => "Time to live exceeded",
+        FragmentReassemblyTimeExceeded = 1 => "Frag reassembly time exceeded",
+    }
+    other => "Time exceeded, Bad Code: {}"
+}
+
+icmp_enum! {
+    /// Codes of an ICMPv4 `Parameter Problem` message.
+    pub enum Icmpv4ParameterProblemCode {
+        PointerIndicatesError = 0 => "Parameter problem: pointer indicates the error",
+        MissingRequiredOption = 1 => "Parameter problem: missing a required option",
+        BadLength = 2 => "Parameter problem: bad length",
+    }
+    other => "Parameter problem, Bad Code: {}"
+}
+
+icmp_enum! {
+    /// ICMPv6 message types.
+    pub enum Icmpv6Type {
+        DestinationUnreachable = 1 => "Destination unreachable",
+        PacketTooBig = 2 => "Packet too big",
+        TimeExceeded = 3 => "Time exceeded",
+        ParameterProblem = 4 => "Parameter problem",
+        EchoRequest = 128 => "Echo request",
+        EchoReply = 129 => "Echo reply",
+        MulticastListenerQuery = 130 => "MLD Query",
+        MulticastListenerReport = 131 => "MLD Report",
+        MulticastListenerDone = 132 => "MLD Reduction",
+        RouterSolicitation = 133 => "Router solicitation",
+        RouterAdvertisement = 134 => "Router advertisement",
+        NeighborSolicitation = 135 => "Neighbor solicitation",
+        NeighborAdvertisement = 136 => "Neighbor advertisement",
+        Redirect = 137 => "Redirect",
+    }
+    other => "Bad ICMP type: {}"
+}
+
+icmp_enum! {
+    /// Codes of an ICMPv6 `Destination Unreachable` message.
+    pub enum Icmpv6UnreachableCode {
+        NoRoute = 0 => "No route",
+        AdminProhibited = 1 => "Administratively prohibited",
+        BeyondScope = 2 => "Beyond scope of source address",
+        AddressUnreachable = 3 => "Address unreachable",
+        PortUnreachable = 4 => "Port unreachable",
+        SourcePolicyFailed = 5 => "Source address failed ingress/egress policy",
+        RejectRoute = 6 => "Reject route to destination",
+        SourceRoutingHeaderError = 7 => "Error in Source Routing Header",
+    }
+    other => "Unknown code {}"
+}
+
+icmp_enum! {
+    /// Codes of an ICMPv6 `Time Exceeded` message.
+    pub enum Icmpv6TimeExceededCode {
+        HopLimitExceeded = 0 => "Hop limit",
+        FragmentReassemblyTimeExceeded = 1 => "Defragmentation failure",
+    }
+    other => "code {}"
+}
+
+icmp_enum! {
+    /// Codes of an ICMPv6 `Parameter Problem` message.
+    pub enum Icmpv6ParameterProblemCode {
+        ErroneousHeaderField = 0 => "Wrong header field",
+        UnrecognizedNextHeader = 1 => "Unknown header",
+        UnrecognizedOption = 2 => "Unknown option",
+    }
+    other => "code {}"
+}
+
+/// An ICMPv4 message type together with its code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Icmpv4Message {
+    EchoReply,
+    DestinationUnreachable(Icmpv4UnreachableCode),
+    SourceQuench,
+    Redirect(Icmpv4RedirectCode),
+    EchoRequest,
+    TimeExceeded(Icmpv4TimeExceededCode),
+    ParameterProblem(Icmpv4ParameterProblemCode),
+    /// Any other type, or a code-less type with a non-zero code.
+    Other {
+        icmp_type: Icmpv4Type,
+        code: u8,
+    },
+}
+
+impl Icmpv4Message {
+    /// Build the message from the raw type and code fields.
+    pub fn new(icmp_type: u8, code: u8) -> Self {
+        match Icmpv4Type::from(icmp_type) {
+            Icmpv4Type::EchoReply if code == 0 => Icmpv4Message::EchoReply,
+            Icmpv4Type::DestinationUnreachable => {
+                Icmpv4Message::DestinationUnreachable(code.into())
+            }
+            Icmpv4Type::SourceQuench if code == 0 => Icmpv4Message::SourceQuench,
+            Icmpv4Type::Redirect => Icmpv4Message::Redirect(code.into()),
+            Icmpv4Type::EchoRequest if code == 0 => Icmpv4Message::EchoRequest,
+            Icmpv4Type::TimeExceeded => Icmpv4Message::TimeExceeded(code.into()),
+            Icmpv4Type::ParameterProblem => Icmpv4Message::ParameterProblem(code.into()),
+            icmp_type => Icmpv4Message::Other { icmp_type, code },
+        }
+    }
+
+    /// Get the message type.
+    pub fn icmp_type(&self) -> Icmpv4Type {
+        match self {
+            Icmpv4Message::EchoReply => Icmpv4Type::EchoReply,
+            Icmpv4Message::DestinationUnreachable(_) => Icmpv4Type::DestinationUnreachable,
+            Icmpv4Message::SourceQuench => Icmpv4Type::SourceQuench,
+            Icmpv4Message::Redirect(_) => Icmpv4Type::Redirect,
+            Icmpv4Message::EchoRequest => Icmpv4Type::EchoRequest,
+            Icmpv4Message::TimeExceeded(_) => Icmpv4Type::TimeExceeded,
+            Icmpv4Message::ParameterProblem(_) => Icmpv4Type::ParameterProblem,
+            Icmpv4Message::Other { icmp_type, .. } => *icmp_type,
+        }
+    }
+
+    /// Get the raw code field.
+    pub fn code(&self) -> u8 {
+        match self {
+            Icmpv4Message::EchoReply | Icmpv4Message::SourceQuench | Icmpv4Message::EchoRequest => {
+                0
+            }
+            Icmpv4Message::DestinationUnreachable(code) => (*code).into(),
+            Icmpv4Message::Redirect(code) => (*code).into(),
+            Icmpv4Message::TimeExceeded(code) => (*code).into(),
+            Icmpv4Message::ParameterProblem(code) => (*code).into(),
+            Icmpv4Message::Other { code, .. } => *code,
+        }
+    }
+}
+
+impl fmt::Display for Icmpv4Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Icmpv4Message::DestinationUnreachable(code) => code.fmt(f),
+            Icmpv4Message::Redirect(code) => code.fmt(f),
+            Icmpv4Message::TimeExceeded(code) => code.fmt(f),
+            Icmpv4Message::ParameterProblem(code) => code.fmt(f),
+            message => message.icmp_type().fmt(f),
+        }
+    }
+}
+
+/// An ICMPv6 message type together with its code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Icmpv6Message {
+    DestinationUnreachable(Icmpv6UnreachableCode),
+    PacketTooBig,
+    TimeExceeded(Icmpv6TimeExceededCode),
+    ParameterProblem(Icmpv6ParameterProblemCode),
+    EchoRequest,
+    EchoReply,
+    /// Any other type, or a code-less type with a non-zero code.
+    Other {
+        icmp_type: Icmpv6Type,
+        code: u8,
+    },
+}
+
+impl Icmpv6Message {
+    /// Build the message from the raw type and code fields.
+    pub fn new(icmp_type: u8, code: u8) -> Self {
+        match Icmpv6Type::from(icmp_type) {
+            Icmpv6Type::DestinationUnreachable => {
+                Icmpv6Message::DestinationUnreachable(code.into())
+            }
+            Icmpv6Type::PacketTooBig if code == 0 => Icmpv6Message::PacketTooBig,
+            Icmpv6Type::TimeExceeded => Icmpv6Message::TimeExceeded(code.into()),
+            Icmpv6Type::ParameterProblem => Icmpv6Message::ParameterProblem(code.into()),
+            Icmpv6Type::EchoRequest if code == 0 => Icmpv6Message::EchoRequest,
+            Icmpv6Type::EchoReply if code == 0 => Icmpv6Message::EchoReply,
+            icmp_type => Icmpv6Message::Other { icmp_type, code },
+        }
+    }
+
+    /// Get the message type.
+    pub fn icmp_type(&self) -> Icmpv6Type {
+        match self {
+            Icmpv6Message::DestinationUnreachable(_) => Icmpv6Type::DestinationUnreachable,
+            Icmpv6Message::PacketTooBig => Icmpv6Type::PacketTooBig,
+            Icmpv6Message::TimeExceeded(_) => Icmpv6Type::TimeExceeded,
+            Icmpv6Message::ParameterProblem(_) => Icmpv6Type::ParameterProblem,
+            Icmpv6Message::EchoRequest => Icmpv6Type::EchoRequest,
+            Icmpv6Message::EchoReply => Icmpv6Type::EchoReply,
+            Icmpv6Message::Other { icmp_type, .. } => *icmp_type,
+        }
+    }
+
+    /// Get the raw code field.
+    pub fn code(&self) -> u8 {
+        match self {
+            Icmpv6Message::PacketTooBig | Icmpv6Message::EchoRequest | Icmpv6Message::EchoReply => {
+                0
+            }
+            Icmpv6Message::DestinationUnreachable(code) => (*code).into(),
+            Icmpv6Message::TimeExceeded(code) => (*code).into(),
+            Icmpv6Message::ParameterProblem(code) => (*code).into(),
+            Icmpv6Message::Other { code, .. } => *code,
+        }
+    }
+}
+
+impl fmt::Display for Icmpv6Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Icmpv6Message::DestinationUnreachable(code) => {
+                write!(f, "Destination unreachable: {}", code)
+            }
+            Icmpv6Message::TimeExceeded(code) => write!(f, "Time exceeded: {}", code),
+            Icmpv6Message::ParameterProblem(code) => write!(f, "Parameter problem: {}", code),
+            message => message.icmp_type().fmt(f),
+        }
+    }
+}
+
+/// The code of a `Destination Unreachable` message of either family.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnreachableCode {
+    V4(Icmpv4UnreachableCode),
+    V6(Icmpv6UnreachableCode),
+}
+
+impl fmt::Display for UnreachableCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnreachableCode::V4(code) => code.fmt(f),
+            UnreachableCode::V6(code) => write!(f, "Destination unreachable: {}", code),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_round_trip() {
+        for value in 0..=u8::MAX {
+            assert_eq!(u8::from(Icmpv4Type::from(value)), value);
+            assert_eq!(u8::from(Icmpv4UnreachableCode::from(value)), value);
+            assert_eq!(u8::from(Icmpv6Type::from(value)), value);
+            assert_eq!(u8::from(Icmpv6UnreachableCode::from(value)), value);
+
+            let message = Icmpv4Message::new(3, value);
+            assert_eq!((u8::from(message.icmp_type()), message.code()), (3, value));
+            let message = Icmpv6Message::new(value, 1);
+            assert_eq!((u8::from(message.icmp_type()), message.code()), (value, 1));
+            let message = Icmpv4Message::new(value, 0);
+            assert_eq!((u8::from(message.icmp_type()), message.code()), (value, 0));
+        }
+    }
+
+    #[test]
+    fn iputils_strings() {
+        assert_eq!(
+            Icmpv4Message::new(3, 1).to_string(),
+            "Destination Host Unreachable"
+        );
+        assert_eq!(
+            Icmpv4Message::new(11, 0).to_string(),
+            "Time to live exceeded"
+        );
+        assert_eq!(Icmpv4Message::new(0, 0).to_string(), "Echo Reply");
+        assert_eq!(Icmpv4Message::new(42, 0).to_string(), "Bad ICMP type: 42");
+        assert_eq!(
+            Icmpv6Message::new(1, 0).to_string(),
+            "Destination unreachable: No route"
+        );
+        assert_eq!(
+            Icmpv6Message::new(3, 0).to_string(),
+            "Time exceeded: Hop limit"
+        );
+        assert_eq!(
+            UnreachableCode::V4(Icmpv4UnreachableCode::PortUnreachable).to_string(),
+            "Destination Port Unreachable"
+        );
+    }
+
+    #[test]
+    fn named_codes() {
+        assert_eq!(
+            Icmpv4Message::new(3, 4),
+            Icmpv4Message::DestinationUnreachable(Icmpv4UnreachableCode::FragmentationNeeded)
+        );
+        assert_eq!(
+            Icmpv4Message::new(3, 13),
+            Icmpv4Message::DestinationUnreachable(Icmpv4UnreachableCode::AdminProhibited)
+        );
+        assert_eq!(
+            Icmpv6Message::new(3, 0),
+            Icmpv6Message::TimeExceeded(Icmpv6TimeExceededCode::HopLimitExceeded)
+        );
+        assert_eq!(
+            Icmpv6Message::new(1, 0),
+            Icmpv6Message::DestinationUnreachable(Icmpv6UnreachableCode::NoRoute)
+        );
+    }
+}
@@ -1,7 +1,17 @@
-use std::fmt;
+use std::{
+    borrow::Cow,
+    fmt,
+    net::IpAddr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::error::{Result, SurgeError};
+
+use self::message::{Icmpv4Message, Icmpv4UnreachableCode, Icmpv6Message, UnreachableCode};
 
 pub mod icmpv4;
 pub mod icmpv6;
+pub mod message;
 
 /// Represents the ICMP reply packet.
 #[derive(Debug)]
@@ -26,6 +36,276 @@ impl IcmpPacket {
             IcmpPacket::V6(packet) => packet.get_sequence(),
         }
     }
+
+    /// Get the address of the host that sent this packet.
+    pub fn get_source(&self) -> IpAddr {
+        match self {
+            IcmpPacket::V4(packet) => IpAddr::V4(packet.get_source()),
+            IcmpPacket::V6(packet) => IpAddr::V6(packet.get_source()),
+        }
+    }
+
+    /// Get the address the original echo request was sent to.
+    ///
+    /// For error messages this is taken from the quoted packet, so it is the
+    /// pinged host even when the reply came from a router along the path.
+    pub fn get_real_dest(&self) -> IpAddr {
+        match self {
+            IcmpPacket::V4(packet) => IpAddr::V4(packet.get_real_dest()),
+            IcmpPacket::V6(packet) => IpAddr::V6(packet.get_real_dest()),
+        }
+    }
+
+    /// Get the type of service byte, or traffic class on IPv6, of the
+    /// received packet, if known.
+    pub fn get_tos(&self) -> Option<u8> {
+        match self {
+            IcmpPacket::V4(packet) => packet.get_tos(),
+            IcmpPacket::V6(packet) => packet.get_traffic_class(),
+        }
+    }
+
+    /// Get the type of service byte, or traffic class on IPv6, of the echo
+    /// request quoted by an ICMP error, if known. `None` for echo replies.
+    pub fn get_quoted_tos(&self) -> Option<u8> {
+        match self {
+            IcmpPacket::V4(packet) => packet.get_quoted_tos(),
+            IcmpPacket::V6(packet) => packet.get_quoted_traffic_class(),
+        }
+    }
+
+    /// Get the data echoed back by the host. Empty for error messages.
+    pub fn get_payload(&self) -> &[u8] {
+        match self {
+            IcmpPacket::V4(packet) => packet.get_payload(),
+            IcmpPacket::V6(packet) => packet.get_payload(),
+        }
+    }
+
+    /// Compare the echoed data with the payload that was sent, returning the
+    /// offset of the first byte that differs. A reply that is shorter or
+    /// longer than the request differs at the end of the shorter one.
+    pub fn payload_mismatch(&self, sent: &[u8]) -> Option<usize> {
+        let received = self.get_payload();
+        match received.iter().zip(sent).position(|(a, b)| a != b) {
+            Some(offset) => Some(offset),
+            None if received.len() != sent.len() => Some(received.len().min(sent.len())),
+            None => None,
+        }
+    }
+
+    /// Read back the [`PayloadStamp`] at the start of the echoed data.
+    ///
+    /// Only meaningful for replies to a pinger that stamps its payloads, see
+    /// [`Pinger::stamp_payload`](crate::Pinger::stamp_payload).
+    pub fn get_stamp(&self) -> Option<PayloadStamp> {
+        PayloadStamp::decode(self.get_payload())
+    }
+
+    /// Whether this packet was sent by an intermediate hop rather than by
+    /// the pinged host itself.
+    pub fn is_from_intermediate_hop(&self) -> bool {
+        self.get_source() != self.get_real_dest()
+    }
+
+    /// Classify what this packet says about the echo request it answers.
+    pub fn outcome(&self) -> PingOutcome {
+        let from = self.get_source();
+        match self {
+            IcmpPacket::V4(packet) => match packet.get_message() {
+                Icmpv4Message::EchoReply => PingOutcome::EchoReply,
+                Icmpv4Message::DestinationUnreachable(
+                    Icmpv4UnreachableCode::FragmentationNeeded,
+                ) => PingOutcome::PacketTooBig {
+                    mtu: packet.get_next_hop_mtu().unwrap_or(0),
+                    from,
+                },
+                Icmpv4Message::DestinationUnreachable(code) => PingOutcome::Unreachable {
+                    code: UnreachableCode::V4(code),
+                    from,
+                },
+                Icmpv4Message::TimeExceeded(_) => PingOutcome::TimeExceeded { from },
+                Icmpv4Message::ParameterProblem(_) => PingOutcome::ParameterProblem { from },
+                message => PingOutcome::Other {
+                    icmp_type: message.icmp_type().into(),
+                    code: message.code(),
+                    from,
+                },
+            },
+            IcmpPacket::V6(packet) => match packet.get_message() {
+                Icmpv6Message::EchoReply => PingOutcome::EchoReply,
+                Icmpv6Message::DestinationUnreachable(code) => PingOutcome::Unreachable {
+                    code: UnreachableCode::V6(code),
+                    from,
+                },
+                Icmpv6Message::PacketTooBig => PingOutcome::PacketTooBig {
+                    mtu: packet.get_mtu().unwrap_or(0),
+                    from,
+                },
+                Icmpv6Message::TimeExceeded(_) => PingOutcome::TimeExceeded { from },
+                Icmpv6Message::ParameterProblem(_) => PingOutcome::ParameterProblem { from },
+                message => PingOutcome::Other {
+                    icmp_type: message.icmp_type().into(),
+                    code: message.code(),
+                    from,
+                },
+            },
+        }
+    }
+}
+
+/// What a matched reply says about an echo request.
+///
+/// ICMPv4 `Fragmentation Needed` is reported as [`PingOutcome::PacketTooBig`],
+/// like its ICMPv6 counterpart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PingOutcome {
+    /// The host answered with an echo reply.
+    EchoReply,
+    /// The host answered, but the echoed data differs from what was sent,
+    /// starting at byte `offset`.
+    Corrupted { offset: usize, from: IpAddr },
+    /// The host or a router on the path reported the destination unreachable.
+    Unreachable { code: UnreachableCode, from: IpAddr },
+    /// A router dropped the request because its TTL or hop limit ran out.
+    TimeExceeded { from: IpAddr },
+    /// The request was rejected because of a bad header field.
+    ParameterProblem { from: IpAddr },
+    /// The request was larger than the MTU of the next hop.
+    PacketTooBig { mtu: u32, from: IpAddr },
+    /// Any other ICMP message quoting the request, e.g. a redirect.
+    Other {
+        icmp_type: u8,
+        code: u8,
+        from: IpAddr,
+    },
+}
+
+impl PingOutcome {
+    /// Whether the host answered with an echo reply.
+    pub fn is_echo_reply(&self) -> bool {
+        matches!(self, PingOutcome::EchoReply)
+    }
+
+    /// Turn anything but an echo reply into the matching `SurgeError`.
+    pub fn into_result(self, seq: PingSequence) -> Result<()> {
+        match self {
+            PingOutcome::EchoReply => Ok(()),
+            PingOutcome::Corrupted { offset, from } => {
+                Err(SurgeError::CorruptedReply { seq, offset, from })
+            }
+            PingOutcome::Unreachable { code, from } => {
+                Err(SurgeError::DestinationUnreachable { seq, code, from })
+            }
+            PingOutcome::TimeExceeded { from } => Err(SurgeError::TimeExceeded { seq, from }),
+            PingOutcome::ParameterProblem { from } => {
+                Err(SurgeError::ParameterProblem { seq, from })
+            }
+            PingOutcome::PacketTooBig { mtu, from } => {
+                Err(SurgeError::PacketTooBig { seq, mtu, from })
+            }
+            PingOutcome::Other {
+                icmp_type,
+                code,
+                from,
+            } => Err(SurgeError::UnexpectedReply {
+                seq,
+                icmp_type,
+                code,
+                from,
+            }),
+        }
+    }
+}
+
+/// A send timestamp and token written at the start of the echo payload.
+///
+/// Like the `timeval` iputils puts in its echo requests, this lets the round
+/// trip time be worked out from the reply alone, e.g. for replies that arrive
+/// after their waiter gave up. The time is the wall clock in nanoseconds since
+/// the Unix epoch and the token tells pingers sharing a socket apart, both
+/// big-endian.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PayloadStamp {
+    pub sent: SystemTime,
+    pub token: u32,
+}
+
+impl PayloadStamp {
+    /// Number of payload bytes the stamp takes up.
+    pub const LEN: usize = 12;
+
+    /// A stamp for a request sent now.
+    pub fn now(token: u32) -> Self {
+        Self {
+            sent: SystemTime::now(),
+            token,
+        }
+    }
+
+    /// Encode the stamp into its payload prefix.
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let nanos = self
+            .sent
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_nanos() as u64);
+        let mut buf = [0; Self::LEN];
+        buf[..8].copy_from_slice(&nanos.to_be_bytes());
+        buf[8..].copy_from_slice(&self.token.to_be_bytes());
+        buf
+    }
+
+    /// Decode a stamp from the start of an echo payload.
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        let buf = payload.get(..Self::LEN)?;
+        let nanos = u64::from_be_bytes(buf[..8].try_into().unwrap());
+        let token = u32::from_be_bytes(buf[8..].try_into().unwrap());
+        Some(Self {
+            sent: UNIX_EPOCH + Duration::from_nanos(nanos),
+            token,
+        })
+    }
+
+    /// Time elapsed between sending the request and `received`.
+    pub fn rtt(&self, received: SystemTime) -> Duration {
+        received.duration_since(self.sent).unwrap_or_default()
+    }
+
+    /// The payload as sent, with the stamp in front if there is one.
+    pub(crate) fn prefix(stamp: Option<Self>, payload: &[u8]) -> Cow<'_, [u8]> {
+        match stamp {
+            Some(stamp) => Cow::Owned([&stamp.encode()[..], payload].concat()),
+            None => Cow::Borrowed(payload),
+        }
+    }
+}
+
+/// Set the two bytes of an echo request at `offset`, which must be even, so
+/// its checksum comes out as `flow` whatever its sequence number and the
+/// rest of the payload.
+///
+/// Routers that balance load per flow hash the ICMP checksum along with the
+/// addresses, so this keeps requests that differ only in their sequence on
+/// one path, as Paris traceroute does. Where the kernel fills in the
+/// identifier (Linux ping sockets) or the checksum (ICMPv6), the checksum
+/// on the wire differs from `flow`, but is just as constant.
+pub(crate) fn fix_checksum(message: &mut [u8], offset: usize, flow: u16) -> Result<()> {
+    if offset < 8 || offset % 2 != 0 || message.len() < offset + 2 {
+        return Err(SurgeError::IncorrectBufferSize);
+    }
+    message[2..4].fill(0);
+    message[offset..offset + 2].fill(0);
+    // The checksum is the complement of the one's complement sum of the
+    // message, so the missing word is `!flow` minus the sum so far.
+    let sum = !pnet_packet::util::checksum(message, 1);
+    let word = ones_complement_add(!flow, !sum);
+    message[offset..offset + 2].copy_from_slice(&word.to_be_bytes());
+    Ok(())
+}
+
+fn ones_complement_add(a: u16, b: u16) -> u16 {
+    let sum = u32::from(a) + u32::from(b);
+    ((sum & 0xffff) + (sum >> 16)) as u16
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
@@ -69,3 +349,74 @@ impl From<u16> for PingSequence {
         Self(seq_cnt)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo_reply(payload: &[u8]) -> IcmpPacket {
+        let mut buf = vec![129, 0, 0, 0, 0x12, 0x34, 0, 5];
+        buf.extend_from_slice(payload);
+        IcmpPacket::V6(icmpv6::Icmpv6Packet::decode(&buf, "2001:db8::8".parse().unwrap()).unwrap())
+    }
+
+    #[test]
+    fn fixed_checksum() {
+        for seq in [0u16, 1, 2, 0x1234, 0xffff] {
+            let mut message = vec![8, 0, 0, 0, 0x12, 0x34];
+            message.extend_from_slice(&seq.to_be_bytes());
+            message.extend_from_slice(&[0xab; 17]);
+            fix_checksum(&mut message, 8, 0xbeef).unwrap();
+            assert_eq!(pnet_packet::util::checksum(&message, 1), 0xbeef);
+        }
+        assert!(fix_checksum(&mut [8, 0, 0, 0, 0, 0, 0, 0, 1], 8, 0xbeef).is_err());
+        assert!(fix_checksum(&mut [0; 16], 9, 0xbeef).is_err());
+    }
+
+    #[test]
+    fn payload_mismatch() {
+        let packet = echo_reply(b"abcdef");
+        assert_eq!(packet.get_payload(), b"abcdef");
+        assert_eq!(packet.payload_mismatch(b"abcdef"), None);
+        assert_eq!(packet.payload_mismatch(b"abXdef"), Some(2));
+        assert_eq!(packet.payload_mismatch(b"abcdefgh"), Some(6));
+        assert_eq!(packet.payload_mismatch(b"abc"), Some(3));
+    }
+
+    #[test]
+    fn payload_stamp_round_trip() {
+        let stamp = PayloadStamp {
+            sent: UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789),
+            token: 0xdead_beef,
+        };
+        let payload = PayloadStamp::prefix(Some(stamp), b"abc");
+        assert_eq!(payload.len(), PayloadStamp::LEN + 3);
+        assert_eq!(&payload[PayloadStamp::LEN..], b"abc");
+
+        let packet = echo_reply(&payload);
+        assert_eq!(packet.get_stamp(), Some(stamp));
+        assert_eq!(
+            stamp.rtt(stamp.sent + Duration::from_millis(3)),
+            Duration::from_millis(3)
+        );
+
+        assert_eq!(PayloadStamp::prefix(None, b"abc").as_ref(), b"abc");
+        assert_eq!(PayloadStamp::decode(b"short"), None);
+    }
+
+    #[test]
+    fn corrupted_outcome() {
+        let from = "2001:db8::8".parse().unwrap();
+        let err = PingOutcome::Corrupted { offset: 2, from }
+            .into_result(PingSequence(5))
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            SurgeError::CorruptedReply {
+                seq: PingSequence(5),
+                offset: 2,
+                ..
+            }
+        ));
+    }
+}
@@ -1,32 +1,55 @@
 mod client;
 mod config;
+mod dual_stack;
 mod error;
+mod histogram;
 mod icmp;
+mod monitor;
 mod ping;
+mod pmtu;
+mod resolve;
+mod stats;
+mod sweep;
+mod sys;
+mod target;
+mod traceroute;
 
 use std::{net::IpAddr, time::Duration};
 
-pub use client::{AsyncSocket, Client};
+pub use client::{AsyncSocket, Client, StrayKind};
 pub use config::{Config, ConfigBuilder};
-pub use error::SurgeError;
+pub use dual_stack::DualStackClient;
+pub use error::{SurgeError, TargetSpecError};
+pub use histogram::{RttHistogram, RttRecorder};
 pub use icmp::{
-    icmpv4::Icmpv4Packet, icmpv6::Icmpv6Packet, IcmpPacket, PingIdentifier, PingSequence,
+    icmpv4::Icmpv4Packet,
+    icmpv6::Icmpv6Packet,
+    message::{
+        Icmpv4Message, Icmpv4ParameterProblemCode, Icmpv4RedirectCode, Icmpv4TimeExceededCode,
+        Icmpv4Type, Icmpv4UnreachableCode, Icmpv6Message, Icmpv6ParameterProblemCode,
+        Icmpv6TimeExceededCode, Icmpv6Type, Icmpv6UnreachableCode, UnreachableCode,
+    },
+    IcmpPacket, PayloadStamp, PingIdentifier, PingOutcome, PingSequence,
 };
-pub use ping::Pinger;
+pub use monitor::{HopStats, MonitorConfig, PathChange, PathMonitor, PathSnapshot};
+pub use ping::{PendingPing, Pinger, StrayReply, StreamConfig};
+pub use pmtu::{PathMtu, PmtuConfig};
 use rand::random;
+pub use resolve::{
+    AddressPreference, HostPinger, HostReply, ResolveConfig, Resolved, Resolver, SystemResolver,
+};
+pub use stats::PingStats;
+pub use sweep::{HostStatus, SweepConfig, SweepResult, Sweeper};
+pub use target::{SpecIter, TargetList, TargetSpec, Targets};
+pub use traceroute::{Hop, HopProbe, TracerouteConfig};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum ICMP {
+    #[default]
     V4,
     V6,
 }
 
-impl Default for ICMP {
-    fn default() -> Self {
-        ICMP::V4
-    }
-}
-
 /// Shortcut method to ping address.
 /// **NOTE**: This function creates a new internal `Client` on each call,
 /// and so should not be used if making many target. Create a
@@ -53,6 +76,6 @@ pub async fn ping(host: IpAddr, payload: &[u8]) -> Result<(IcmpPacket, Duration)
         IpAddr::V6(_) => Config::builder().kind(ICMP::V6).build(),
     };
     let client = Client::new(&config)?;
-    let mut pinger = client.pinger(host, PingIdentifier(random())).await;
+    let pinger = client.pinger(host, PingIdentifier(random())).await;
     pinger.ping(PingSequence(0), payload).await
 }
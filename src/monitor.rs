@@ -0,0 +1,445 @@
+use std::{collections::VecDeque, net::IpAddr, sync::Arc, time::Duration};
+
+use futures::future;
+use parking_lot::Mutex;
+use rand::random;
+use tokio::{
+    task::{self, JoinHandle},
+    time::{self, MissedTickBehavior},
+};
+use tracing::debug;
+
+use crate::{
+    client::Client,
+    icmp::{IcmpPacket, PingIdentifier, PingOutcome, PingSequence},
+    ping::Pinger,
+    stats::PingStats,
+};
+
+/// How many path changes a monitor remembers.
+const PATH_CHANGES: usize = 64;
+/// After how many rounds in a row without an answer at the end of the path
+/// the monitor probes up to `max_hops` again.
+const END_LOST_ROUNDS: u32 = 3;
+
+/// The answer to one probe, `None` if it timed out.
+type Answer = Option<(PingOutcome, IcmpPacket, Duration)>;
+
+/// Settings for [`PathMonitor::start`].
+#[derive(Debug, Clone)]
+pub struct MonitorConfig {
+    interval: Duration,
+    timeout: Duration,
+    max_hops: u8,
+    payload: Vec<u8>,
+    flow: Option<u16>,
+}
+
+impl Default for MonitorConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(2),
+            max_hops: 30,
+            payload: vec![0; 56],
+            flow: None,
+        }
+    }
+}
+
+impl MonitorConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Time between two rounds of probes. Zero starts each round as soon
+    /// as the previous one ends. (default: 1s)
+    pub fn interval(mut self, interval: Duration) -> Self {
+        // `time::interval` panics on a zero period.
+        self.interval = interval.max(Duration::from_nanos(1));
+        self
+    }
+
+    /// How long to wait for the answer to each probe. A round ends when
+    /// all its probes are answered or timed out. (default: 2s)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Highest TTL probed until the host answers. (default: 30)
+    pub fn max_hops(mut self, ttl: u8) -> Self {
+        self.max_hops = ttl.max(1);
+        self
+    }
+
+    /// Data sent in every request. (default: 56 zero bytes)
+    pub fn payload(mut self, payload: &[u8]) -> Self {
+        self.payload = payload.to_vec();
+        self
+    }
+
+    /// Keep every probe on one of several load-balanced paths, see
+    /// [`TracerouteConfig::paris`](crate::TracerouteConfig::paris). Without
+    /// it, routers that balance per flow show up as path changes. (default:
+    /// off)
+    pub fn paris(mut self, flow: u16) -> Self {
+        self.flow = Some(flow);
+        self
+    }
+}
+
+/// Rolling statistics of one hop, see [`PathMonitor::snapshot`].
+#[derive(Debug, Clone)]
+pub struct HopStats {
+    pub ttl: u8,
+    /// Loss and last/avg/best/worst/stddev round trip times of the probes
+    /// sent with this TTL, whoever answered them.
+    pub stats: PingStats,
+    /// Every router or host that answered at this TTL, in the order they
+    /// were first seen.
+    pub responders: Vec<IpAddr>,
+    /// Who answered the latest probe that got an answer.
+    pub last_responder: Option<IpAddr>,
+}
+
+impl HopStats {
+    fn new(ttl: u8) -> HopStats {
+        HopStats {
+            ttl,
+            stats: PingStats::new(),
+            responders: Vec::new(),
+            last_responder: None,
+        }
+    }
+}
+
+/// A hop that started answering from a different address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathChange {
+    /// Number of the round it was seen in, counting from 1.
+    pub round: u64,
+    pub ttl: u8,
+    pub from: IpAddr,
+    pub to: IpAddr,
+}
+
+/// The state of a [`PathMonitor`] at one point in time.
+#[derive(Debug, Clone)]
+pub struct PathSnapshot {
+    pub host: IpAddr,
+    /// Completed rounds of probes.
+    pub rounds: u64,
+    /// One entry per TTL, up to the one where the host answers, or up to
+    /// `max_hops` while it has not answered yet.
+    pub hops: Vec<HopStats>,
+    /// The most recent path changes, oldest first.
+    pub changes: Vec<PathChange>,
+}
+
+struct PathState {
+    rounds: u64,
+    hops: Vec<HopStats>,
+    /// TTL at which the host answered most recently.
+    path_len: Option<u8>,
+    /// Rounds in a row in which the probe at `path_len` got no answer.
+    end_lost: u32,
+    changes: VecDeque<PathChange>,
+}
+
+impl PathState {
+    fn record_round(&mut self, answers: Vec<(u8, Answer)>) {
+        self.rounds += 1;
+        // Anything but Time Exceeded means the probe got no further.
+        let end = answers
+            .iter()
+            .filter_map(|(ttl, answer)| answer.as_ref().map(|answer| (ttl, answer)))
+            .find(|(_, (outcome, _, _))| !matches!(outcome, PingOutcome::TimeExceeded { .. }))
+            .map(|(ttl, _)| *ttl);
+        if let Some(end) = end {
+            self.path_len = Some(end);
+            self.end_lost = 0;
+            self.hops.truncate(end.into());
+        } else if let Some(path_len) = self.path_len {
+            let last = answers
+                .iter()
+                .find(|(ttl, _)| *ttl == path_len)
+                .map(|(_, answer)| answer);
+            // The probe that used to reach the host expired on the way, or
+            // nothing answers there any more, so the path may have got
+            // longer: probe up to `max_hops` again.
+            let grew = match last {
+                Some(Some((PingOutcome::TimeExceeded { .. }, _, _))) => true,
+                Some(None) => {
+                    self.end_lost += 1;
+                    self.end_lost >= END_LOST_ROUNDS
+                }
+                _ => false,
+            };
+            if grew {
+                self.path_len = None;
+                self.end_lost = 0;
+            }
+        }
+
+        for (ttl, answer) in answers {
+            if self.path_len.is_some_and(|path_len| ttl > path_len) {
+                continue;
+            }
+            while self.hops.len() < ttl.into() {
+                let next = self.hops.len() as u8 + 1;
+                self.hops.push(HopStats::new(next));
+            }
+            let hop = &mut self.hops[usize::from(ttl) - 1];
+            let Some((_, packet, rtt)) = answer else {
+                hop.stats.record_timeout();
+                continue;
+            };
+            hop.stats.record_reply(rtt);
+            let responder = packet.get_source();
+            if !hop.responders.contains(&responder) {
+                hop.responders.push(responder);
+            }
+            if let Some(from) = hop.last_responder.filter(|from| *from != responder) {
+                if self.changes.len() == PATH_CHANGES {
+                    self.changes.pop_front();
+                }
+                self.changes.push_back(PathChange {
+                    round: self.rounds,
+                    ttl,
+                    from,
+                    to: responder,
+                });
+            }
+            hop.last_responder = Some(responder);
+        }
+    }
+}
+
+/// Probes every hop on the path to a host over and over, like `mtr`, and
+/// keeps rolling statistics per hop.
+///
+/// Each round sends one echo request per TTL at once, from 1 up to the TTL
+/// where the host answered last, or `max_hops` until it has. Whenever that
+/// TTL gets a Time Exceeded, or no answer for a few rounds in a row, the
+/// path may have grown longer and rounds go up to `max_hops` again. The
+/// monitor runs in a background task until it is dropped.
+pub struct PathMonitor {
+    host: IpAddr,
+    state: Arc<Mutex<PathState>>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for PathMonitor {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl PathMonitor {
+    /// Start monitoring the path to `host` through `client`, which can be
+    /// shared with any number of other monitors and pingers.
+    ///
+    /// The TTL is set per request, so this is only supported on Linux and
+    /// Android.
+    pub async fn start(client: &Client, host: IpAddr, config: MonitorConfig) -> PathMonitor {
+        let mut pinger = client.pinger(host, PingIdentifier(random())).await;
+        pinger.timeout(config.timeout);
+        let state = Arc::new(Mutex::new(PathState {
+            rounds: 0,
+            hops: Vec::new(),
+            path_len: None,
+            end_lost: 0,
+            changes: VecDeque::new(),
+        }));
+        let task = task::spawn(monitor_task(pinger, config, state.clone()));
+        PathMonitor { host, state, task }
+    }
+
+    /// The statistics of every hop so far.
+    pub fn snapshot(&self) -> PathSnapshot {
+        let state = self.state.lock();
+        PathSnapshot {
+            host: self.host,
+            rounds: state.rounds,
+            hops: state.hops.clone(),
+            changes: state.changes.iter().cloned().collect(),
+        }
+    }
+}
+
+async fn monitor_task(pinger: Pinger, config: MonitorConfig, state: Arc<Mutex<PathState>>) {
+    let mut ticker = time::interval(config.interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut seq = 0u16;
+    loop {
+        ticker.tick().await;
+        let last_ttl = state.lock().path_len.unwrap_or(config.max_hops);
+        let mut probes = Vec::with_capacity(last_ttl.into());
+        for ttl in 1..=last_ttl {
+            let mut meta = pinger.send_meta;
+            meta.hop_limit = Some(ttl.into());
+            let sent = pinger
+                .send_with(PingSequence(seq), &config.payload, meta, config.flow)
+                .await;
+            seq = seq.wrapping_add(1);
+            probes.push(async move {
+                let answer = match sent {
+                    Ok(pending) => pending.await.ok(),
+                    Err(e) => {
+                        debug!("error sending probe with ttl {}: {}", ttl, e);
+                        None
+                    }
+                };
+                (ttl, answer)
+            });
+        }
+        let answers = future::join_all(probes).await;
+        state.lock().record_round(answers);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::icmp::icmpv4::Icmpv4Packet;
+
+    fn answer(from: &str, outcome: PingOutcome) -> Answer {
+        // An echo reply from `from`, which is all `record_round` looks at.
+        let buf = [0, 0, 0, 0, 0, 1, 0, 1];
+        let packet = Icmpv4Packet::decode(
+            &buf,
+            socket2::Type::DGRAM,
+            from.parse().unwrap(),
+            "10.0.0.1".parse().unwrap(),
+        )
+        .unwrap();
+        Some((outcome, IcmpPacket::V4(packet), Duration::from_millis(1)))
+    }
+
+    fn time_exceeded(from: &str) -> Answer {
+        let outcome = PingOutcome::TimeExceeded {
+            from: from.parse().unwrap(),
+        };
+        answer(from, outcome)
+    }
+
+    #[test]
+    fn rounds() {
+        let mut state = PathState {
+            rounds: 0,
+            hops: Vec::new(),
+            path_len: None,
+            end_lost: 0,
+            changes: VecDeque::new(),
+        };
+        state.record_round(vec![
+            (1, time_exceeded("10.0.0.254")),
+            (2, None),
+            (3, answer("10.9.9.9", PingOutcome::EchoReply)),
+            (4, answer("10.9.9.9", PingOutcome::EchoReply)),
+        ]);
+        assert_eq!(state.path_len, Some(3));
+        assert_eq!(state.hops.len(), 3);
+        assert_eq!(state.hops[1].stats.loss(), 100.0);
+
+        state.record_round(vec![
+            (1, time_exceeded("10.0.0.253")),
+            (2, time_exceeded("10.1.0.1")),
+            (3, answer("10.9.9.9", PingOutcome::EchoReply)),
+        ]);
+        let hop = &state.hops[0];
+        assert_eq!(hop.stats.transmitted(), 2);
+        assert_eq!(hop.stats.last(), Some(Duration::from_millis(1)));
+        assert_eq!(
+            hop.responders,
+            ["10.0.0.254", "10.0.0.253"].map(|addr| addr.parse::<IpAddr>().unwrap())
+        );
+        assert_eq!(state.hops[1].stats.loss(), 50.0);
+        assert_eq!(
+            Vec::from(state.changes.clone()),
+            [PathChange {
+                round: 2,
+                ttl: 1,
+                from: "10.0.0.254".parse().unwrap(),
+                to: "10.0.0.253".parse().unwrap(),
+            }]
+        );
+    }
+
+    #[test]
+    fn path_grows() {
+        let mut state = PathState {
+            rounds: 0,
+            hops: Vec::new(),
+            path_len: None,
+            end_lost: 0,
+            changes: VecDeque::new(),
+        };
+        state.record_round(vec![
+            (1, time_exceeded("10.0.0.254")),
+            (2, answer("10.9.9.9", PingOutcome::EchoReply)),
+            (3, answer("10.9.9.9", PingOutcome::EchoReply)),
+        ]);
+        assert_eq!(state.path_len, Some(2));
+
+        // Only TTLs 1 and 2 are probed, and the host is no longer at 2.
+        state.record_round(vec![
+            (1, time_exceeded("10.0.0.254")),
+            (2, time_exceeded("10.1.0.1")),
+        ]);
+        assert_eq!(state.path_len, None);
+        assert_eq!(
+            Vec::from(state.changes.clone()),
+            [PathChange {
+                round: 2,
+                ttl: 2,
+                from: "10.9.9.9".parse().unwrap(),
+                to: "10.1.0.1".parse().unwrap(),
+            }]
+        );
+
+        state.record_round(vec![
+            (1, time_exceeded("10.0.0.254")),
+            (2, time_exceeded("10.1.0.1")),
+            (3, answer("10.9.9.9", PingOutcome::EchoReply)),
+            (4, answer("10.9.9.9", PingOutcome::EchoReply)),
+        ]);
+        assert_eq!(state.path_len, Some(3));
+        assert_eq!(state.hops.len(), 3);
+        assert_eq!(state.hops[2].stats.transmitted(), 1);
+        assert_eq!(state.changes.len(), 1);
+    }
+
+    #[test]
+    fn end_of_path_lost() {
+        let mut state = PathState {
+            rounds: 0,
+            hops: Vec::new(),
+            path_len: None,
+            end_lost: 0,
+            changes: VecDeque::new(),
+        };
+        state.record_round(vec![
+            (1, time_exceeded("10.0.0.254")),
+            (2, answer("10.9.9.9", PingOutcome::EchoReply)),
+        ]);
+        assert_eq!(state.path_len, Some(2));
+
+        // The router now at TTL 2 does not answer.
+        for _ in 1..END_LOST_ROUNDS {
+            state.record_round(vec![(1, time_exceeded("10.0.0.254")), (2, None)]);
+            assert_eq!(state.path_len, Some(2));
+        }
+        state.record_round(vec![(1, time_exceeded("10.0.0.254")), (2, None)]);
+        assert_eq!(state.path_len, None);
+
+        state.record_round(vec![
+            (1, time_exceeded("10.0.0.254")),
+            (2, None),
+            (3, answer("10.9.9.9", PingOutcome::EchoReply)),
+        ]);
+        assert_eq!(state.path_len, Some(3));
+        assert_eq!(state.hops.len(), 3);
+    }
+}
@@ -1,34 +1,142 @@
 use std::{
+    collections::{HashSet, VecDeque},
+    future::Future,
     net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
     time::{Duration, Instant},
 };
 
-use tokio::time::timeout;
+use futures::{
+    future::{self, BoxFuture, FutureExt},
+    stream::{self, FuturesUnordered, Stream, StreamExt},
+};
+use parking_lot::Mutex;
+use tokio::{
+    sync::mpsc::{self, Receiver},
+    time::{self, timeout, MissedTickBehavior},
+};
 
 use crate::{
-    client::{AsyncSocket, ReplyMap},
+    client::{AsyncSocket, Reply, ReplyMap, StrayKind, StraySender, TxId},
     error::{Result, SurgeError},
-    icmp::{icmpv4, icmpv6, IcmpPacket, PingIdentifier, PingSequence},
+    histogram::RttRecorder,
+    icmp::{icmpv4, icmpv6, IcmpPacket, PayloadStamp, PingIdentifier, PingOutcome, PingSequence},
     is_linux_icmp_socket,
+    sys::SendMeta,
+    ICMP,
 };
 
+/// How many stray replies a pinger buffers until they are collected.
+const STRAY_REPLIES: usize = 64;
+
+/// How many send times a pinger remembers to time stray replies with.
+const SENT_TIMES: usize = 1024;
+
+/// A reply that no request was waiting for anymore, see
+/// [`Pinger::stray_replies`].
+#[derive(Debug)]
+pub struct StrayReply {
+    pub kind: StrayKind,
+    pub packet: IcmpPacket,
+    /// Time since the request was sent, if the pinger still remembers it.
+    pub rtt: Option<Duration>,
+}
+
+/// Settings for [`Pinger::stream`].
+#[derive(Debug, Clone)]
+pub struct StreamConfig {
+    interval: Duration,
+    count: Option<usize>,
+    deadline: Option<Duration>,
+    payload: Vec<u8>,
+    first_sequence: PingSequence,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            count: None,
+            deadline: None,
+            payload: vec![0; 56],
+            first_sequence: PingSequence(0),
+        }
+    }
+}
+
+impl StreamConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Time between two requests, regardless of how long replies take. Zero
+    /// sends them back to back. (default: 1s)
+    pub fn interval(mut self, interval: Duration) -> Self {
+        // `time::interval` panics on a zero period.
+        self.interval = interval.max(Duration::from_nanos(1));
+        self
+    }
+
+    /// Stop after sending `count` requests, once their replies are in or
+    /// have timed out. (default: unlimited)
+    pub fn count(mut self, count: usize) -> Self {
+        self.count = Some(count);
+        self
+    }
+
+    /// End the stream this long after it starts, even if replies are still
+    /// outstanding. (default: none)
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Data sent in every request. (default: 56 zero bytes)
+    pub fn payload(mut self, payload: &[u8]) -> Self {
+        self.payload = payload.to_vec();
+        self
+    }
+
+    /// Sequence number of the first request; later ones count up from it and
+    /// wrap around at `u16::MAX`. (default: 0)
+    pub fn first_sequence(mut self, seq: PingSequence) -> Self {
+        self.first_sequence = seq;
+        self
+    }
+}
+
+/// What a request resolves to, see [`Pinger::probe`].
+type Probed = (PingOutcome, IcmpPacket, Duration);
+
+/// The sequences of one pinger that are waiting for a reply.
+type InFlight = Arc<Mutex<HashSet<PingSequence>>>;
+
 /// A Ping struct represents the state of one particular ping instance.
 pub struct Pinger {
     pub host: IpAddr,
     pub ident: Option<PingIdentifier>,
     scope_id: u32,
     timeout: Duration,
-    socket: AsyncSocket,
+    pub(crate) send_meta: SendMeta,
+    pub(crate) socket: AsyncSocket,
     reply_map: ReplyMap,
-    last_sequence: Option<PingSequence>,
+    in_flight: InFlight,
+    pub(crate) token: Option<u32>,
+    strays_tx: StraySender,
+    strays_rx: Receiver<(StrayKind, Reply)>,
+    sent_times: Arc<Mutex<VecDeque<(PingSequence, Instant)>>>,
+    histogram: Option<RttRecorder>,
+    client_histogram: Arc<Mutex<Option<RttRecorder>>>,
 }
 
 impl Drop for Pinger {
     fn drop(&mut self) {
-        if let Some(sequence) = self.last_sequence.take() {
-            // Ensure no reply waiter is left hanging if this pinger is dropped while
-            // waiting for a reply.
-            self.reply_map.remove(self.host, self.ident, sequence);
+        // Ensure no reply waiter is left hanging if this pinger is dropped while
+        // waiting for replies.
+        for seq in self.in_flight.lock().drain() {
+            self.reply_map.remove(self.host, self.ident, seq);
         }
     }
 }
@@ -39,6 +147,7 @@ impl Pinger {
         ident_hint: PingIdentifier,
         socket: AsyncSocket,
         response_map: ReplyMap,
+        client_histogram: Arc<Mutex<Option<RttRecorder>>>,
     ) -> Pinger {
         let ident = if is_linux_icmp_socket!(socket.get_type()) {
             None
@@ -46,14 +155,22 @@ impl Pinger {
             Some(ident_hint)
         };
 
+        let (strays_tx, strays_rx) = mpsc::channel(STRAY_REPLIES);
         Pinger {
             host,
             ident,
             scope_id: 0,
             timeout: Duration::from_secs(2),
+            send_meta: SendMeta::default(),
             socket,
             reply_map: response_map,
-            last_sequence: None,
+            in_flight: InFlight::default(),
+            token: None,
+            strays_tx,
+            strays_rx,
+            sent_times: Arc::default(),
+            histogram: None,
+            client_histogram,
         }
     }
 
@@ -69,52 +186,299 @@ impl Pinger {
         self
     }
 
+    /// Send this pinger's requests with the given TTL or hop limit instead
+    /// of the socket's, without affecting other pingers of the client.
+    ///
+    /// Only supported on Linux and Android; elsewhere sending fails.
+    pub fn ttl(&mut self, ttl: u32) -> &mut Pinger {
+        self.send_meta.hop_limit = Some(ttl);
+        self
+    }
+
+    /// Send this pinger's requests with the given type of service byte, or
+    /// traffic class on IPv6, instead of the socket's, without affecting
+    /// other pingers of the client.
+    ///
+    /// Only supported on Linux and Android; elsewhere sending fails.
+    pub fn tos(&mut self, tos: u8) -> &mut Pinger {
+        self.send_meta.tos = Some(tos);
+        self
+    }
+
+    /// Prefix every echo payload with a [`PayloadStamp`] carrying the send
+    /// time and `token`, so replies can be timed from their content alone.
+    pub fn stamp_payload(&mut self, token: u32) -> &mut Pinger {
+        self.token = Some(token);
+        self
+    }
+
+    /// Record the round trip time of every echo reply this pinger gets in
+    /// `recorder`, in addition to the client's histogram, if any.
+    pub fn histogram(&mut self, recorder: RttRecorder) -> &mut Pinger {
+        self.histogram = Some(recorder);
+        self
+    }
+
     /// Send Ping request with sequence number.
-    pub async fn ping(
-        &mut self,
+    ///
+    /// Only an echo reply that carries `payload` back intact counts as
+    /// success. ICMP errors that quote the request, such as `Destination
+    /// Unreachable`, and corrupted replies are returned as the matching
+    /// `SurgeError` variant; use [`Pinger::probe`] to get them as a
+    /// [`PingOutcome`] instead.
+    pub async fn ping(&self, seq: PingSequence, payload: &[u8]) -> Result<(IcmpPacket, Duration)> {
+        let (outcome, packet, rtt) = self.probe(seq, payload).await?;
+        outcome.into_result(seq)?;
+        Ok((packet, rtt))
+    }
+
+    /// Send Ping request with sequence number and classify whatever answers it.
+    ///
+    /// Unlike [`Pinger::ping`], ICMP errors and corrupted replies are returned
+    /// as `Ok` with the matching [`PingOutcome`]. Timeouts and socket errors
+    /// are still `Err`.
+    pub async fn probe(
+        &self,
         seq: PingSequence,
         payload: &[u8],
-    ) -> Result<(IcmpPacket, Duration)> {
-        // Register to wait for a reply.
-        let reply_waiter = self.reply_map.new_waiter(self.host, self.ident, seq)?;
+    ) -> Result<(PingOutcome, IcmpPacket, Duration)> {
+        self.send(seq, payload).await?.await
+    }
 
-        // Send actual packet
-        if let Err(e) = self.send_ping(seq, payload).await {
-            self.reply_map.remove(self.host, self.ident, seq);
-            return Err(e);
+    /// Send Ping request with sequence number without waiting for the reply.
+    ///
+    /// The returned [`PendingPing`] resolves like [`Pinger::probe`] once the
+    /// reply arrives or the timeout expires, so any number of sequences can
+    /// be in flight at once. Dropping it stops waiting for that reply, and
+    /// dropping the pinger stops waiting for all of them.
+    pub async fn send(&self, seq: PingSequence, payload: &[u8]) -> Result<PendingPing> {
+        self.send_with(seq, payload, self.send_meta, None).await
+    }
+
+    /// [`Pinger::send`] with the given per-packet options, and the checksum
+    /// fixed to `flow` if set.
+    pub(crate) async fn send_with(
+        &self,
+        seq: PingSequence,
+        payload: &[u8],
+        meta: SendMeta,
+        flow: Option<u16>,
+    ) -> Result<PendingPing> {
+        let (host, ident) = (self.host, self.ident);
+        if !self.in_flight.lock().insert(seq) {
+            return Err(SurgeError::IdenticalRequests { host, ident, seq });
         }
+        // Register to wait for a reply.
+        let reply_waiter = match self
+            .reply_map
+            .new_waiter(host, ident, seq, self.strays_tx.clone())
+        {
+            Ok(reply_waiter) => reply_waiter,
+            Err(e) => {
+                self.in_flight.lock().remove(&seq);
+                return Err(e);
+            }
+        };
+        // From here on, dropping `waiting` unregisters the waiter again.
+        let waiting = Waiting {
+            reply_map: self.reply_map.clone(),
+            host,
+            ident,
+            seq,
+            in_flight: self.in_flight.clone(),
+        };
 
+        // Send actual packet
+        let stamp = self.token.map(PayloadStamp::now);
+        // Taken before sending: the receive task may see the reply before
+        // the send call returns.
         let send_time = Instant::now();
-        self.last_sequence = Some(seq);
-
-        // Wait for reply or timeout.
-        match timeout(self.timeout, reply_waiter).await {
-            Ok(Ok(reply)) => Ok((
-                reply.packet,
-                reply.timestamp.saturating_duration_since(send_time),
-            )),
-            Ok(Err(_err)) => Err(SurgeError::NetworkError),
-            Err(_) => {
-                self.reply_map.remove(self.host, self.ident, seq);
-                Err(SurgeError::Timeout { seq })
+        let (tx_id, mut sent) = self.send_request(seq, payload, stamp, &meta, flow).await?;
+        // Replies echo the payload, which is everything after the header.
+        sent.drain(..8);
+        let timeout_duration = self.timeout;
+        let sent_times = self.sent_times.clone();
+        let histogram = self.histogram.clone();
+        let client_histogram = self.client_histogram.clone();
+        let reply = async move {
+            // Wait for reply or timeout.
+            let result = timeout(timeout_duration, reply_waiter).await;
+            // Prefer the time the kernel actually sent the request, if we know it.
+            let send_time = tx_id
+                .and_then(|id| waiting.reply_map.take_sent(id))
+                .unwrap_or(send_time);
+            {
+                let mut sent_times = sent_times.lock();
+                if sent_times.len() == SENT_TIMES {
+                    sent_times.pop_front();
+                }
+                sent_times.push_back((seq, send_time));
             }
-        }
+            match result {
+                Ok(Ok(reply)) => {
+                    waiting.finish();
+                    let mut outcome = reply.packet.outcome();
+                    if outcome.is_echo_reply() {
+                        if let Some(offset) = reply.packet.payload_mismatch(&sent) {
+                            outcome = PingOutcome::Corrupted {
+                                offset,
+                                from: reply.packet.get_source(),
+                            };
+                        }
+                    }
+                    let rtt = reply.timestamp.saturating_duration_since(send_time);
+                    if matches!(
+                        outcome,
+                        PingOutcome::EchoReply | PingOutcome::Corrupted { .. }
+                    ) {
+                        if let Some(histogram) = &histogram {
+                            histogram.record(rtt);
+                        }
+                        if let Some(histogram) = &*client_histogram.lock() {
+                            histogram.record(rtt);
+                        }
+                    }
+                    Ok((outcome, reply.packet, rtt))
+                }
+                // The client was destroyed while waiting.
+                Ok(Err(_err)) => Err(SurgeError::ClientDestroyed),
+                Err(_) => {
+                    if waiting.finish() {
+                        waiting.reply_map.expire(host, ident, seq);
+                    }
+                    Err(SurgeError::Timeout { seq })
+                }
+            }
+        };
+
+        Ok(PendingPing {
+            seq,
+            reply: Box::pin(reply),
+        })
+    }
+
+    /// Send a request every `config.interval` and yield the outcome of each
+    /// one as its reply arrives or its timeout expires.
+    ///
+    /// Requests are sent on schedule even while earlier ones are still
+    /// waiting for a reply, so results can arrive out of order.
+    pub fn stream(
+        &self,
+        config: StreamConfig,
+    ) -> impl Stream<Item = (PingSequence, Result<(PingOutcome, IcmpPacket, Duration)>)> + Unpin + '_
+    {
+        let mut ticker = time::interval(config.interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let state = StreamState {
+            ticker,
+            deadline: config
+                .deadline
+                .map(|deadline| time::Instant::now() + deadline),
+            remaining: config.count,
+            next_sequence: config.first_sequence,
+            pending: FuturesUnordered::new(),
+            payload: config.payload,
+        };
+        Box::pin(stream::unfold(state, move |mut state| async move {
+            let deadline = state.deadline;
+            let deadline = async move {
+                match deadline {
+                    Some(deadline) => time::sleep_until(deadline).await,
+                    None => future::pending().await,
+                }
+            };
+            tokio::pin!(deadline);
+            loop {
+                let sending = state.remaining != Some(0);
+                if !sending && state.pending.is_empty() {
+                    return None;
+                }
+                tokio::select! {
+                    biased;
+
+                    _ = &mut deadline => return None,
+                    Some(result) = state.pending.next() => return Some((result, state)),
+                    _ = state.ticker.tick(), if sending => {
+                        let seq = state.next_sequence;
+                        state.next_sequence = PingSequence(seq.0.wrapping_add(1));
+                        if let Some(remaining) = &mut state.remaining {
+                            *remaining -= 1;
+                        }
+                        match self.send(seq, &state.payload).await {
+                            Ok(pending) => state
+                                .pending
+                                .push(pending.map(move |result| (seq, result)).boxed()),
+                            Err(e) => return Some(((seq, Err(e)), state)),
+                        }
+                    }
+                }
+            }
+        }))
+    }
+
+    /// Collect the replies that arrived for requests that were no longer
+    /// waited for: duplicates of answered requests (ping's `DUP!`) and
+    /// replies to requests that already timed out.
+    ///
+    /// Requests are remembered for a while after they are answered or time
+    /// out; replies that come later than that are not reported. Up to 64
+    /// stray replies are kept until they are collected.
+    pub fn stray_replies(&mut self) -> impl Iterator<Item = StrayReply> + '_ {
+        std::iter::from_fn(move || {
+            let (kind, reply) = self.strays_rx.try_recv().ok()?;
+            let seq = reply.packet.get_sequence();
+            let rtt = self
+                .sent_times
+                .lock()
+                .iter()
+                .rev()
+                .find(|(sent_seq, _)| *sent_seq == seq)
+                .map(|(_, sent)| reply.timestamp.saturating_duration_since(*sent));
+            Some(StrayReply {
+                kind,
+                packet: reply.packet,
+                rtt,
+            })
+        })
     }
 
     /// Send a ping packet (useful, when you don't need a reply).
     pub async fn send_ping(&self, seq: PingSequence, payload: &[u8]) -> Result<()> {
+        let stamp = self.token.map(PayloadStamp::now);
+        self.send_request(seq, payload, stamp, &self.send_meta, None)
+            .await?;
+        Ok(())
+    }
+
+    /// Send a ping packet and return the key of its transmit timestamp, if
+    /// the socket has them enabled, along with the packet.
+    async fn send_request(
+        &self,
+        seq: PingSequence,
+        payload: &[u8],
+        stamp: Option<PayloadStamp>,
+        meta: &SendMeta,
+        flow: Option<u16>,
+    ) -> Result<(Option<TxId>, Vec<u8>)> {
+        if self.host.is_ipv4() != (self.socket.kind() == ICMP::V4) {
+            return Err(SurgeError::AddressFamilyMismatch { host: self.host });
+        }
         // Create and send ping packet.
-        let mut packet = match self.host {
+        let packet = match self.host {
             IpAddr::V4(_) => icmpv4::make_icmpv4_echo_packet(
                 self.ident.unwrap_or(PingIdentifier(0)),
                 seq,
                 self.socket.get_type(),
                 payload,
+                stamp,
+                flow,
             )?,
             IpAddr::V6(_) => icmpv6::make_icmpv6_echo_packet(
                 self.ident.unwrap_or(PingIdentifier(0)),
                 seq,
                 payload,
+                stamp,
+                flow,
             )?,
         };
 
@@ -123,8 +487,68 @@ impl Pinger {
             sa.set_scope_id(self.scope_id);
         }
 
-        self.socket.send_to(&mut packet, &target).await?;
+        let (_, tx_id) = self.socket.send_msg(&packet, &target, meta).await?;
 
-        Ok(())
+        Ok((tx_id, packet))
+    }
+}
+
+/// The state of a [`Pinger::stream`] between two items.
+struct StreamState {
+    ticker: time::Interval,
+    deadline: Option<time::Instant>,
+    remaining: Option<usize>,
+    next_sequence: PingSequence,
+    pending: FuturesUnordered<BoxFuture<'static, (PingSequence, Result<Probed>)>>,
+    payload: Vec<u8>,
+}
+
+/// An echo request waiting for its reply, see [`Pinger::send`].
+///
+/// Resolves to the same result as [`Pinger::probe`]. Dropping it before then
+/// stops waiting for the reply.
+#[must_use = "a ping does nothing with its reply unless polled"]
+pub struct PendingPing {
+    seq: PingSequence,
+    reply: Pin<Box<dyn Future<Output = Result<Probed>> + Send>>,
+}
+
+impl PendingPing {
+    /// The sequence number of the request.
+    pub fn sequence(&self) -> PingSequence {
+        self.seq
+    }
+}
+
+impl Future for PendingPing {
+    type Output = Result<Probed>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.reply.as_mut().poll(cx)
+    }
+}
+
+/// Unregisters the waiter of a request that is dropped before it is done.
+struct Waiting {
+    reply_map: ReplyMap,
+    host: IpAddr,
+    ident: Option<PingIdentifier>,
+    seq: PingSequence,
+    in_flight: InFlight,
+}
+
+impl Waiting {
+    /// Mark the request as done. Returns false if the pinger was dropped,
+    /// which already unregistered the waiter.
+    fn finish(&self) -> bool {
+        self.in_flight.lock().remove(&self.seq)
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        if self.finish() {
+            self.reply_map.remove(self.host, self.ident, self.seq);
+        }
     }
 }
@@ -0,0 +1,314 @@
+use std::{io, net::IpAddr};
+
+use crate::{
+    error::{Result, SurgeError},
+    icmp::{PayloadStamp, PingOutcome, PingSequence},
+    ping::Pinger,
+};
+
+/// The smallest MTU every IPv4 link must support (RFC 791).
+const MIN_MTU_V4: u32 = 68;
+/// The smallest MTU every IPv6 link must support (RFC 8200).
+const MIN_MTU_V6: u32 = 1280;
+
+/// Settings for [`Pinger::discover_mtu`].
+#[derive(Debug, Clone)]
+pub struct PmtuConfig {
+    target: u32,
+    retries: u32,
+}
+
+impl Default for PmtuConfig {
+    fn default() -> Self {
+        Self {
+            target: 1500,
+            retries: 2,
+        }
+    }
+}
+
+impl PmtuConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The largest MTU tried, in bytes including the IP header. (default:
+    /// 1500)
+    pub fn target(mut self, mtu: u32) -> Self {
+        self.target = mtu;
+        self
+    }
+
+    /// How many more requests of a size are sent after one times out before
+    /// the size counts as lost. (default: 2)
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+}
+
+/// What [`Pinger::discover_mtu`] found out about the path to a host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathMtu {
+    /// The largest packet, IP header included, that got an echo reply.
+    pub mtu: u32,
+    /// The next-hop MTU of the latest `Fragmentation Needed` or `Packet Too
+    /// Big` answer, and the router that sent it.
+    pub reported: Option<(u32, IpAddr)>,
+    /// Requests larger than `mtu` were lost without any error coming back:
+    /// a router on the path drops them silently, or its errors are filtered.
+    pub black_hole: bool,
+    /// How many requests were sent.
+    pub probes: u32,
+}
+
+/// What the requests of one size got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SizeOutcome {
+    /// An echo reply.
+    Fits,
+    /// An error saying the size is too big, with the next-hop MTU and the
+    /// router that reported it, if any.
+    TooBig(Option<(u32, IpAddr)>),
+    /// Every request timed out.
+    Lost,
+}
+
+/// Where a discovery is: the largest size known to fit and the smallest
+/// known not to.
+struct Search {
+    min: u32,
+    fits: u32,
+    too_big: u32,
+    seq: u16,
+    result: PathMtu,
+}
+
+impl Search {
+    fn new(min: u32, target: u32) -> Search {
+        Search {
+            min,
+            fits: 0,
+            too_big: target + 1,
+            seq: 0,
+            result: PathMtu {
+                mtu: 0,
+                reported: None,
+                black_hole: false,
+                probes: 0,
+            },
+        }
+    }
+
+    /// Record what the requests of `size` bytes got and return the next size
+    /// to try, or `None` once the search is over.
+    fn record(&mut self, size: u32, outcome: SizeOutcome) -> Option<u32> {
+        match outcome {
+            SizeOutcome::Fits => self.fits = size,
+            SizeOutcome::TooBig(reported) => {
+                self.too_big = size;
+                if reported.is_some() {
+                    self.result.reported = reported;
+                }
+            }
+            SizeOutcome::Lost => {
+                self.too_big = size;
+                self.result.black_hole = true;
+            }
+        }
+        if self.fits == 0 {
+            // The smallest size tells a black hole from a dead host.
+            return (self.too_big > self.min).then_some(self.min);
+        }
+        if self.too_big - self.fits <= 1 {
+            return None;
+        }
+        match self.result.reported {
+            Some((mtu, _)) if mtu > self.fits && mtu < self.too_big => Some(mtu),
+            _ => Some(self.fits + (self.too_big - self.fits) / 2),
+        }
+    }
+
+    /// The result of a search that is over, or a timeout if even the
+    /// smallest size got no echo reply.
+    fn finish(mut self) -> Result<PathMtu> {
+        if self.fits == 0 {
+            return Err(SurgeError::Timeout {
+                seq: PingSequence(self.seq.wrapping_sub(1)),
+            });
+        }
+        self.result.mtu = self.fits;
+        Ok(self.result)
+    }
+}
+
+impl Pinger {
+    /// Find the path MTU to the host: send echo requests that must not be
+    /// fragmented and binary-search their size between the smallest MTU of
+    /// the address family and `config.target`.
+    ///
+    /// The next-hop MTU that routers report in `Fragmentation Needed` or
+    /// `Packet Too Big` answers is tried next, so a path that reports its
+    /// bottleneck needs only a few requests. Sizes whose requests all time
+    /// out are treated as too big and flagged as a black hole.
+    ///
+    /// The client must be created with
+    /// [`ConfigBuilder::dont_fragment`](crate::ConfigBuilder::dont_fragment).
+    /// Fails if even the smallest size gets no echo reply.
+    pub async fn discover_mtu(&self, config: PmtuConfig) -> Result<PathMtu> {
+        if !self.socket.dont_fragment() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the client does not set Don't Fragment",
+            )
+            .into());
+        }
+        let min = match self.host {
+            IpAddr::V4(_) => MIN_MTU_V4,
+            IpAddr::V6(_) => MIN_MTU_V6,
+        };
+        let target = config.target.max(min);
+        let mut search = Search::new(min, target);
+
+        // Most paths carry the target, so try it before anything else.
+        let mut next = Some(target);
+        while let Some(size) = next {
+            let outcome = self.probe_size(size, &config, &mut search).await?;
+            next = search.record(size, outcome);
+        }
+        search.finish()
+    }
+
+    /// Send requests of `size` bytes until one is answered or all retries
+    /// are lost.
+    async fn probe_size(
+        &self,
+        size: u32,
+        config: &PmtuConfig,
+        search: &mut Search,
+    ) -> Result<SizeOutcome> {
+        let header = match self.host {
+            IpAddr::V4(_) => 20 + 8,
+            IpAddr::V6(_) => 40 + 8,
+        };
+        let stamp_len = self.token.map_or(0, |_| PayloadStamp::LEN);
+        let payload = vec![0; (size as usize).saturating_sub(header + stamp_len)];
+        for _ in 0..=config.retries {
+            let seq = PingSequence(search.seq);
+            search.seq = search.seq.wrapping_add(1);
+            search.result.probes += 1;
+            match self.probe(seq, &payload).await {
+                Ok((PingOutcome::EchoReply | PingOutcome::Corrupted { .. }, _, _)) => {
+                    return Ok(SizeOutcome::Fits);
+                }
+                Ok((PingOutcome::PacketTooBig { mtu, from }, _, _)) => {
+                    return Ok(SizeOutcome::TooBig(Some((mtu, from))));
+                }
+                Ok((outcome, _, _)) => {
+                    outcome.into_result(seq)?;
+                }
+                Err(SurgeError::Timeout { .. }) => {}
+                // Too big for the outgoing interface.
+                Err(e) if is_message_too_long(&e) => return Ok(SizeOutcome::TooBig(None)),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(SizeOutcome::Lost)
+    }
+}
+
+/// Whether sending failed because the request is larger than the MTU of the
+/// outgoing interface.
+fn is_message_too_long(e: &SurgeError) -> bool {
+    #[cfg(unix)]
+    if let SurgeError::IOError(e) = e {
+        return e.raw_os_error() == Some(libc::EMSGSIZE);
+    }
+    let _ = e;
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run a search between `min` and `target` over a path that answers each
+    /// size with `path`, and return its result and the sizes tried.
+    fn search(
+        min: u32,
+        target: u32,
+        path: impl Fn(u32) -> SizeOutcome,
+    ) -> (Result<PathMtu>, Vec<u32>) {
+        let mut search = Search::new(min, target);
+        let mut tried = vec![];
+        let mut next = Some(target);
+        while let Some(size) = next {
+            tried.push(size);
+            next = search.record(size, path(size));
+        }
+        (search.finish(), tried)
+    }
+
+    #[test]
+    fn target_fits() {
+        let (result, tried) = search(MIN_MTU_V4, 1500, |_| SizeOutcome::Fits);
+        assert_eq!(result.unwrap().mtu, 1500);
+        assert_eq!(tried, [1500]);
+    }
+
+    #[test]
+    fn binary_search() {
+        let path = |size| match size {
+            ..=1400 => SizeOutcome::Fits,
+            _ => SizeOutcome::TooBig(None),
+        };
+        let (result, tried) = search(MIN_MTU_V4, 1500, path);
+        let result = result.unwrap();
+        assert_eq!(result.mtu, 1400);
+        assert_eq!(result.reported, None);
+        assert!(!result.black_hole);
+        assert_eq!(tried[..3], [1500, 68, 784]);
+        // Halves the interval each time.
+        assert!(tried.len() <= 2 + 11, "{tried:?}");
+    }
+
+    #[test]
+    fn jump_to_reported_mtu() {
+        let router = "10.0.0.254".parse().unwrap();
+        let path = |size| match size {
+            ..=1400 => SizeOutcome::Fits,
+            _ => SizeOutcome::TooBig(Some((1400, router))),
+        };
+        let (result, tried) = search(MIN_MTU_V4, 1500, path);
+        let result = result.unwrap();
+        assert_eq!(result.mtu, 1400);
+        assert_eq!(result.reported, Some((1400, router)));
+        assert!(!result.black_hole);
+        assert_eq!(tried[..3], [1500, 68, 1400]);
+    }
+
+    #[test]
+    fn black_hole() {
+        let path = |size| match size {
+            ..=1400 => SizeOutcome::Fits,
+            _ => SizeOutcome::Lost,
+        };
+        let (result, _) = search(MIN_MTU_V6, 1500, path);
+        let result = result.unwrap();
+        assert_eq!(result.mtu, 1400);
+        assert_eq!(result.reported, None);
+        assert!(result.black_hole);
+    }
+
+    #[test]
+    fn dead_host() {
+        let (result, tried) = search(MIN_MTU_V4, 1500, |_| SizeOutcome::Lost);
+        assert!(matches!(result, Err(SurgeError::Timeout { .. })));
+        assert_eq!(tried, [1500, 68]);
+
+        // A target at the minimum is only tried once.
+        let (result, tried) = search(MIN_MTU_V6, MIN_MTU_V6, |_| SizeOutcome::Lost);
+        assert!(matches!(result, Err(SurgeError::Timeout { .. })));
+        assert_eq!(tried, [MIN_MTU_V6]);
+    }
+}
@@ -0,0 +1,384 @@
+use std::{
+    io,
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::future::BoxFuture;
+use tracing::debug;
+
+use crate::{
+    dual_stack::DualStackClient,
+    error::{Result, SurgeError},
+    icmp::{IcmpPacket, PingIdentifier, PingOutcome, PingSequence},
+    ping::Pinger,
+};
+
+/// How long stale addresses are used after a failed resolution before the
+/// name is resolved again.
+const RETRY_RESOLVE: Duration = Duration::from_secs(5);
+
+/// The addresses a [`Resolver`] found for a host name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Resolved {
+    pub addrs: Vec<IpAddr>,
+    /// How long the addresses may be used before the name is resolved
+    /// again, if the resolver knows.
+    pub ttl: Option<Duration>,
+}
+
+/// Turns host names into addresses for a [`HostPinger`].
+///
+/// Implement it to use a DNS client that reports record TTLs, or a fixed
+/// table in tests.
+pub trait Resolver: Send + Sync {
+    fn resolve<'a>(&'a self, host: &'a str) -> BoxFuture<'a, io::Result<Resolved>>;
+}
+
+/// The system resolver, through `tokio::net::lookup_host`. It does not
+/// report TTLs.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve<'a>(&'a self, host: &'a str) -> BoxFuture<'a, io::Result<Resolved>> {
+        Box::pin(async move {
+            let mut addrs = Vec::new();
+            for addr in tokio::net::lookup_host((host, 0)).await? {
+                if !addrs.contains(&addr.ip()) {
+                    addrs.push(addr.ip());
+                }
+            }
+            Ok(Resolved { addrs, ttl: None })
+        })
+    }
+}
+
+/// Which of a host's addresses are pinged, and in which order they are
+/// tried.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AddressPreference {
+    /// In the order the resolver returned them.
+    #[default]
+    Resolver,
+    Ipv4First,
+    Ipv6First,
+    Ipv4Only,
+    Ipv6Only,
+    /// Alternate between the families, starting with IPv6, like Happy
+    /// Eyeballs (RFC 8305).
+    HappyEyeballs,
+}
+
+impl AddressPreference {
+    fn order(self, addrs: Vec<IpAddr>) -> Vec<IpAddr> {
+        let addrs: Vec<_> = addrs.into_iter().map(|addr| addr.to_canonical()).collect();
+        let (v4, v6): (Vec<_>, Vec<_>) = addrs.iter().copied().partition(IpAddr::is_ipv4);
+        match self {
+            AddressPreference::Resolver => addrs,
+            AddressPreference::Ipv4First => v4.into_iter().chain(v6).collect(),
+            AddressPreference::Ipv6First => v6.into_iter().chain(v4).collect(),
+            AddressPreference::Ipv4Only => v4,
+            AddressPreference::Ipv6Only => v6,
+            AddressPreference::HappyEyeballs => {
+                let mut ordered = Vec::with_capacity(v4.len() + v6.len());
+                let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+                loop {
+                    match (v6.next(), v4.next()) {
+                        (None, None) => break ordered,
+                        (a, b) => ordered.extend(a.into_iter().chain(b)),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Settings for [`HostPinger::new`].
+#[derive(Debug, Clone)]
+pub struct ResolveConfig {
+    refresh: Duration,
+    preference: AddressPreference,
+}
+
+impl Default for ResolveConfig {
+    fn default() -> Self {
+        Self {
+            refresh: Duration::from_secs(60),
+            preference: AddressPreference::default(),
+        }
+    }
+}
+
+impl ResolveConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolve the name again this often, unless the resolver reports a
+    /// TTL. (default: 60s)
+    pub fn refresh(mut self, refresh: Duration) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    /// Which addresses to ping, in which order. (default:
+    /// [`AddressPreference::Resolver`])
+    pub fn preference(mut self, preference: AddressPreference) -> Self {
+        self.preference = preference;
+        self
+    }
+}
+
+/// The answer to one request of a [`HostPinger`].
+#[derive(Debug)]
+pub struct HostReply {
+    /// The address the request was sent to, `None` if the name could not be
+    /// resolved.
+    pub addr: Option<IpAddr>,
+    pub result: Result<(PingOutcome, IcmpPacket, Duration)>,
+}
+
+/// Pings a host by name, resolving it again whenever its addresses expire.
+///
+/// Requests go to the first address in the order of the
+/// [`AddressPreference`]. When one gets no echo reply, the next request goes
+/// to the next address, and every new resolution starts over at the first.
+pub struct HostPinger {
+    host: String,
+    client: DualStackClient,
+    ident: PingIdentifier,
+    resolver: Arc<dyn Resolver>,
+    config: ResolveConfig,
+    timeout: Duration,
+    addrs: Vec<IpAddr>,
+    /// Index in `addrs` of the address the next request goes to.
+    current: usize,
+    /// When to resolve the name again, `None` until it is first resolved.
+    expires: Option<Instant>,
+    pinger: Option<Pinger>,
+}
+
+impl HostPinger {
+    /// Ping `host` through `client`, resolving it with the
+    /// [`SystemResolver`]. Nothing is resolved until the first request.
+    pub fn new(
+        client: &DualStackClient,
+        host: &str,
+        ident: PingIdentifier,
+        config: ResolveConfig,
+    ) -> HostPinger {
+        HostPinger {
+            host: host.to_string(),
+            client: client.clone(),
+            ident,
+            resolver: Arc::new(SystemResolver),
+            config,
+            timeout: Duration::from_secs(2),
+            addrs: Vec::new(),
+            current: 0,
+            expires: None,
+            pinger: None,
+        }
+    }
+
+    /// Resolve the name with `resolver` from the next request on.
+    pub fn resolver(&mut self, resolver: impl Resolver + 'static) -> &mut HostPinger {
+        self.resolver = Arc::new(resolver);
+        self.expires = None;
+        self
+    }
+
+    /// The timeout of each Ping. (default: 2s)
+    pub fn timeout(&mut self, timeout: Duration) -> &mut HostPinger {
+        self.timeout = timeout;
+        if let Some(pinger) = &mut self.pinger {
+            pinger.timeout(timeout);
+        }
+        self
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The addresses of the host in the order they are tried, as of the
+    /// latest successful resolution.
+    pub fn addresses(&self) -> &[IpAddr] {
+        &self.addrs
+    }
+
+    /// Resolve the name if its addresses expired, send a request to the
+    /// current address and classify whatever answers it, like
+    /// [`Pinger::probe`].
+    pub async fn probe(&mut self, seq: PingSequence, payload: &[u8]) -> HostReply {
+        if let Err(e) = self.refresh().await {
+            return HostReply {
+                addr: None,
+                result: Err(e),
+            };
+        }
+        let addr = self.addrs[self.current];
+        let pinger = match self.pinger.take() {
+            Some(pinger) if pinger.host == addr => pinger,
+            _ => match self.client.pinger(addr, self.ident).await {
+                Ok(mut pinger) => {
+                    pinger.timeout(self.timeout);
+                    pinger
+                }
+                Err(e) => {
+                    self.current = (self.current + 1) % self.addrs.len();
+                    return HostReply {
+                        addr: Some(addr),
+                        result: Err(e.into()),
+                    };
+                }
+            },
+        };
+        let result = self.pinger.insert(pinger).probe(seq, payload).await;
+        if !matches!(
+            result,
+            Ok((PingOutcome::EchoReply | PingOutcome::Corrupted { .. }, _, _))
+        ) {
+            self.current = (self.current + 1) % self.addrs.len();
+        }
+        HostReply {
+            addr: Some(addr),
+            result,
+        }
+    }
+
+    /// Resolve the name if it is due. A failure is only returned if there
+    /// are no earlier addresses to fall back on.
+    async fn refresh(&mut self) -> Result<()> {
+        let now = Instant::now();
+        if self.expires.is_some_and(|expires| now < expires) {
+            return Ok(());
+        }
+        let resolved = self
+            .resolver
+            .resolve(&self.host)
+            .await
+            .and_then(|resolved| {
+                let addrs = self.config.preference.order(resolved.addrs);
+                if addrs.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        "no address of the preferred family",
+                    ));
+                }
+                Ok((addrs, resolved.ttl.unwrap_or(self.config.refresh)))
+            });
+        match resolved {
+            Ok((addrs, ttl)) => {
+                if addrs != self.addrs {
+                    debug!("{} resolved to {:?}", self.host, addrs);
+                }
+                self.addrs = addrs;
+                self.current = 0;
+                self.expires = Some(now + ttl);
+                Ok(())
+            }
+            Err(e) if !self.addrs.is_empty() => {
+                debug!(
+                    "cannot resolve {}, keeping {:?}: {}",
+                    self.host, self.addrs, e
+                );
+                self.expires = Some(now + RETRY_RESOLVE.min(self.config.refresh));
+                Ok(())
+            }
+            Err(source) => Err(SurgeError::ResolveFailed {
+                host: self.host.clone(),
+                source,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+
+    fn addrs(addrs: &[&str]) -> Vec<IpAddr> {
+        addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    /// Hands out the next of a list of answers on every call.
+    struct FakeResolver(Mutex<Vec<io::Result<Resolved>>>);
+
+    impl Resolver for FakeResolver {
+        fn resolve<'a>(&'a self, _host: &'a str) -> BoxFuture<'a, io::Result<Resolved>> {
+            let answer = self.0.lock().remove(0);
+            Box::pin(async move { answer })
+        }
+    }
+
+    #[test]
+    fn preference_order() {
+        let resolved = addrs(&["10.0.0.1", "2001:db8::1", "::ffff:10.0.0.2", "2001:db8::2"]);
+        let order = |preference: AddressPreference| preference.order(resolved.clone());
+        assert_eq!(
+            order(AddressPreference::Resolver),
+            addrs(&["10.0.0.1", "2001:db8::1", "10.0.0.2", "2001:db8::2"])
+        );
+        assert_eq!(
+            order(AddressPreference::Ipv6First),
+            addrs(&["2001:db8::1", "2001:db8::2", "10.0.0.1", "10.0.0.2"])
+        );
+        assert_eq!(
+            order(AddressPreference::Ipv4Only),
+            addrs(&["10.0.0.1", "10.0.0.2"])
+        );
+        assert_eq!(
+            AddressPreference::HappyEyeballs.order(addrs(&[
+                "10.0.0.1",
+                "10.0.0.2",
+                "10.0.0.3",
+                "2001:db8::1"
+            ])),
+            addrs(&["2001:db8::1", "10.0.0.1", "10.0.0.2", "10.0.0.3"])
+        );
+    }
+
+    #[tokio::test]
+    async fn refresh() {
+        let config = ResolveConfig::new()
+            .refresh(Duration::from_secs(3600))
+            .preference(AddressPreference::Ipv4Only);
+        let mut pinger = HostPinger::new(
+            &DualStackClient::default(),
+            "example.test",
+            PingIdentifier(1),
+            config,
+        );
+        pinger.resolver(FakeResolver(Mutex::new(vec![
+            Err(io::ErrorKind::TimedOut.into()),
+            Ok(Resolved {
+                addrs: addrs(&["2001:db8::1", "10.0.0.1"]),
+                ttl: Some(Duration::ZERO),
+            }),
+            Err(io::ErrorKind::TimedOut.into()),
+            Ok(Resolved {
+                addrs: addrs(&["10.0.0.2"]),
+                ttl: None,
+            }),
+        ])));
+
+        assert!(matches!(
+            pinger.refresh().await,
+            Err(SurgeError::ResolveFailed { .. })
+        ));
+        pinger.refresh().await.unwrap();
+        assert_eq!(pinger.addresses(), addrs(&["10.0.0.1"]));
+        // The TTL expired right away, but the old address outlives a failure.
+        pinger.refresh().await.unwrap();
+        assert_eq!(pinger.addresses(), addrs(&["10.0.0.1"]));
+        pinger.expires = None;
+        pinger.refresh().await.unwrap();
+        assert_eq!(pinger.addresses(), addrs(&["10.0.0.2"]));
+        // Not due again for an hour.
+        pinger.refresh().await.unwrap();
+    }
+}
@@ -0,0 +1,401 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    client::StrayKind, error::Result, icmp::IcmpPacket, ping::StrayReply, PingOutcome, SurgeError,
+};
+
+/// Weight of the newest sample in [`PingStats::ewma`], the same 1/8 as
+/// iputils `ping`.
+const EWMA_WEIGHT: f64 = 1.0 / 8.0;
+/// Gain of the RFC 3550 interarrival jitter estimator.
+const JITTER_GAIN: f64 = 1.0 / 16.0;
+
+/// Running statistics over the results of a series of pings, as printed by
+/// `ping` when it exits.
+///
+/// Feed it the results of [`Pinger::ping`](crate::Pinger::ping),
+/// [`Pinger::probe`](crate::Pinger::probe) or
+/// [`Pinger::stream`](crate::Pinger::stream), and format it with `{}` to get
+/// the iputils summary:
+///
+/// ```text
+/// 5 packets transmitted, 4 received, +1 errors, 20% packet loss, time 4005ms
+/// rtt min/avg/max/mdev = 0.036/0.047/0.061/0.008 ms
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PingStats {
+    transmitted: u64,
+    received: u64,
+    errors: u64,
+    duplicates: u64,
+    min: Option<Duration>,
+    max: Option<Duration>,
+    /// Sum of the round trip times and of their squares, in nanoseconds.
+    sum: u128,
+    sum_sq: u128,
+    last: Option<Duration>,
+    jitter: f64,
+    ewma: Option<f64>,
+    first_at: Option<Instant>,
+    last_at: Option<Instant>,
+}
+
+impl PingStats {
+    pub fn new() -> PingStats {
+        PingStats::default()
+    }
+
+    /// Record the result of [`Pinger::probe`](crate::Pinger::probe) or one
+    /// item of [`Pinger::stream`](crate::Pinger::stream).
+    pub fn record(&mut self, result: &Result<(PingOutcome, IcmpPacket, Duration)>) {
+        match result {
+            Ok((outcome, _, rtt)) => self.record_outcome(outcome, *rtt),
+            Err(e) => self.record_error(e),
+        }
+    }
+
+    /// Record the result of [`Pinger::ping`](crate::Pinger::ping).
+    pub fn record_ping(&mut self, result: &Result<(IcmpPacket, Duration)>) {
+        match result {
+            Ok((_, rtt)) => self.record_reply(*rtt),
+            Err(e) => self.record_error(e),
+        }
+    }
+
+    /// Record what a request got back after `rtt`. A corrupted echo reply
+    /// still counts as received, anything else as an error.
+    pub fn record_outcome(&mut self, outcome: &PingOutcome, rtt: Duration) {
+        match outcome {
+            PingOutcome::EchoReply | PingOutcome::Corrupted { .. } => self.record_reply(rtt),
+            _ => {
+                self.sent();
+                self.errors += 1;
+            }
+        }
+    }
+
+    /// Record an echo reply that came back after `rtt`.
+    pub fn record_reply(&mut self, rtt: Duration) {
+        self.sent();
+        self.received += 1;
+        self.min = Some(self.min.map_or(rtt, |min| min.min(rtt)));
+        self.max = Some(self.max.map_or(rtt, |max| max.max(rtt)));
+        let nanos = rtt.as_nanos();
+        self.sum += nanos;
+        self.sum_sq += nanos * nanos;
+
+        let secs = rtt.as_secs_f64();
+        if let Some(last) = self.last {
+            let d = (secs - last.as_secs_f64()).abs();
+            self.jitter += (d - self.jitter) * JITTER_GAIN;
+        }
+        self.last = Some(rtt);
+        self.ewma = Some(match self.ewma {
+            Some(ewma) => ewma + (secs - ewma) * EWMA_WEIGHT,
+            None => secs,
+        });
+    }
+
+    /// Record a request that got no answer.
+    pub fn record_timeout(&mut self) {
+        self.sent();
+    }
+
+    /// Record a failed request. ICMP errors are counted as errors, and a
+    /// timeout or a client destroyed while waiting as a plain loss. Failures
+    /// before the request was sent, such as [`SurgeError::IdenticalRequests`]
+    /// or a socket error, are ignored.
+    pub fn record_error(&mut self, error: &SurgeError) {
+        match error {
+            SurgeError::Timeout { .. } | SurgeError::ClientDestroyed => self.sent(),
+            SurgeError::DestinationUnreachable { .. }
+            | SurgeError::CorruptedReply { .. }
+            | SurgeError::TimeExceeded { .. }
+            | SurgeError::ParameterProblem { .. }
+            | SurgeError::PacketTooBig { .. }
+            | SurgeError::UnexpectedReply { .. } => {
+                self.sent();
+                self.errors += 1;
+            }
+            SurgeError::IdenticalRequests { .. }
+            | SurgeError::IOError(_)
+            | SurgeError::AddressFamilyMismatch { .. }
+            | SurgeError::ResolveFailed { .. }
+            | SurgeError::IncorrectBufferSize
+            | SurgeError::MalformedPacket(_)
+            | SurgeError::EchoRequestPacket
+            | SurgeError::NetworkError => {}
+        }
+    }
+
+    /// Record a reply from [`Pinger::stray_replies`](crate::Pinger::stray_replies).
+    /// Duplicates are counted; late replies are ignored since their request
+    /// was already counted as lost.
+    pub fn record_stray(&mut self, stray: &StrayReply) {
+        if stray.kind == StrayKind::Duplicate {
+            self.duplicates += 1;
+        }
+    }
+
+    fn sent(&mut self) {
+        let now = Instant::now();
+        self.first_at.get_or_insert(now);
+        self.last_at = Some(now);
+        self.transmitted += 1;
+    }
+
+    /// Add the statistics of another series, e.g. of another pinger.
+    ///
+    /// Jitter and EWMA depend on the order of the samples, so the merged
+    /// values are the averages of both, weighted by the number of replies.
+    pub fn merge(&mut self, other: &PingStats) {
+        let (ours, theirs) = (self.received as f64, other.received as f64);
+        if ours + theirs > 0.0 {
+            self.jitter = (self.jitter * ours + other.jitter * theirs) / (ours + theirs);
+        }
+        self.ewma = match (self.ewma, other.ewma) {
+            (Some(a), Some(b)) => Some((a * ours + b * theirs) / (ours + theirs)),
+            (a, b) => a.or(b),
+        };
+        self.last = self.last.or(other.last);
+
+        self.transmitted += other.transmitted;
+        self.received += other.received;
+        self.errors += other.errors;
+        self.duplicates += other.duplicates;
+        self.min = min_opt(self.min, other.min);
+        self.max = max_opt(self.max, other.max);
+        self.sum += other.sum;
+        self.sum_sq += other.sum_sq;
+        self.first_at = min_opt(self.first_at, other.first_at);
+        self.last_at = max_opt(self.last_at, other.last_at);
+    }
+
+    pub fn transmitted(&self) -> u64 {
+        self.transmitted
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// Requests answered with an ICMP error instead of an echo reply.
+    pub fn errors(&self) -> u64 {
+        self.errors
+    }
+
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    /// Percentage of requests that got no echo reply, 0 if none were sent.
+    pub fn loss(&self) -> f64 {
+        if self.transmitted == 0 {
+            return 0.0;
+        }
+        (self.transmitted - self.received) as f64 * 100.0 / self.transmitted as f64
+    }
+
+    /// Round trip time of the most recent reply.
+    pub fn last(&self) -> Option<Duration> {
+        self.last
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.min
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.max
+    }
+
+    pub fn avg(&self) -> Option<Duration> {
+        self.mean_nanos()
+            .map(|avg| Duration::from_nanos(avg as u64))
+    }
+
+    /// Standard deviation of the round trip times, what iputils calls `mdev`.
+    pub fn stddev(&self) -> Option<Duration> {
+        let avg = self.mean_nanos()?;
+        let variance = self.sum_sq as f64 / self.received as f64 - avg * avg;
+        Some(Duration::from_nanos(variance.max(0.0).sqrt() as u64))
+    }
+
+    /// RFC 3550 interarrival jitter: the smoothed difference between
+    /// consecutive round trip times.
+    pub fn jitter(&self) -> Option<Duration> {
+        self.last?;
+        Some(Duration::from_secs_f64(self.jitter))
+    }
+
+    /// Exponentially weighted moving average of the round trip times.
+    pub fn ewma(&self) -> Option<Duration> {
+        self.ewma.map(Duration::from_secs_f64)
+    }
+
+    /// Time between the first and the last recorded request.
+    pub fn elapsed(&self) -> Duration {
+        match (self.first_at, self.last_at) {
+            (Some(first), Some(last)) => last - first,
+            _ => Duration::ZERO,
+        }
+    }
+
+    fn mean_nanos(&self) -> Option<f64> {
+        if self.received == 0 {
+            return None;
+        }
+        Some(self.sum as f64 / self.received as f64)
+    }
+}
+
+fn min_opt<T: Ord>(a: Option<T>, b: Option<T>) -> Option<T> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn max_opt<T: Ord>(a: Option<T>, b: Option<T>) -> Option<T> {
+    a.max(b)
+}
+
+/// Format like C's `%g`: six significant digits, trailing zeros dropped.
+fn format_g(value: f64) -> String {
+    if value == 0.0 {
+        return "0".to_owned();
+    }
+    let digits = 5 - value.abs().log10().floor() as i32;
+    let s = format!("{:.*}", digits.max(0) as usize, value);
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.').to_owned()
+    } else {
+        s
+    }
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+impl fmt::Display for PingStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} packets transmitted, {} received",
+            self.transmitted, self.received
+        )?;
+        if self.duplicates > 0 {
+            write!(f, ", +{} duplicates", self.duplicates)?;
+        }
+        if self.errors > 0 {
+            write!(f, ", +{} errors", self.errors)?;
+        }
+        if self.transmitted > 0 {
+            write!(f, ", {}% packet loss", format_g(self.loss()))?;
+        }
+        write!(f, ", time {}ms", self.elapsed().as_millis())?;
+        if let (Some(min), Some(avg), Some(max), Some(mdev)) =
+            (self.min(), self.avg(), self.max(), self.stddev())
+        {
+            write!(
+                f,
+                "\nrtt min/avg/max/mdev = {:.3}/{:.3}/{:.3}/{:.3} ms",
+                millis(min),
+                millis(avg),
+                millis(max),
+                millis(mdev)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn empty() {
+        let stats = PingStats::new();
+        assert_eq!(stats.loss(), 0.0);
+        assert_eq!(stats.avg(), None);
+        assert_eq!(stats.stddev(), None);
+        assert_eq!(stats.jitter(), None);
+        assert_eq!(
+            stats.to_string(),
+            "0 packets transmitted, 0 received, time 0ms"
+        );
+    }
+
+    #[test]
+    fn replies_and_losses() {
+        let mut stats = PingStats::new();
+        for rtt in [10, 20, 30] {
+            stats.record_reply(ms(rtt));
+        }
+        stats.record_error(&SurgeError::Timeout {
+            seq: crate::PingSequence(3),
+        });
+        stats.record_error(&SurgeError::TimeExceeded {
+            seq: crate::PingSequence(4),
+            from: "10.0.0.1".parse().unwrap(),
+        });
+        // Lost when the client went away.
+        stats.record_error(&SurgeError::ClientDestroyed);
+        // Never sent.
+        stats.record_error(&SurgeError::AddressFamilyMismatch {
+            host: "::1".parse().unwrap(),
+        });
+        stats.record_error(&io::Error::from(io::ErrorKind::PermissionDenied).into());
+
+        assert_eq!(stats.transmitted(), 6);
+        assert_eq!(stats.received(), 3);
+        assert_eq!(stats.errors(), 1);
+        assert_eq!(stats.loss(), 50.0);
+        assert_eq!(stats.min(), Some(ms(10)));
+        assert_eq!(stats.avg(), Some(ms(20)));
+        assert_eq!(stats.max(), Some(ms(30)));
+        // sqrt(((10-20)² + 0 + (30-20)²) / 3) ms
+        assert_eq!(stats.stddev().unwrap().as_micros(), 8164);
+        // Two steps of 10ms: 10/16, then 0.625 + (10 - 0.625)/16 ms.
+        assert_eq!(stats.jitter().unwrap().as_micros(), 1210);
+        // 10, then 10 + 10/8, then 11.25 + 18.75/8 ms.
+        assert_eq!(stats.ewma().unwrap().as_micros(), 13593);
+
+        let summary = stats.to_string();
+        assert!(summary
+            .starts_with("6 packets transmitted, 3 received, +1 errors, 50% packet loss, time "));
+        assert!(summary.ends_with("\nrtt min/avg/max/mdev = 10.000/20.000/30.000/8.165 ms"));
+    }
+
+    #[test]
+    fn merge() {
+        let mut a = PingStats::new();
+        a.record_reply(ms(10));
+        a.record_timeout();
+        let mut b = PingStats::new();
+        b.record_reply(ms(30));
+        b.duplicates = 2;
+
+        a.merge(&b);
+        assert_eq!(a.transmitted(), 3);
+        assert_eq!(a.received(), 2);
+        assert_eq!(a.duplicates(), 2);
+        assert_eq!(a.min(), Some(ms(10)));
+        assert_eq!(a.max(), Some(ms(30)));
+        assert_eq!(a.avg(), Some(ms(20)));
+        assert_eq!(a.ewma(), Some(ms(20)));
+        assert!(a
+            .to_string()
+            .starts_with("3 packets transmitted, 2 received, +2 duplicates, 33.3333% packet loss"));
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    io,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::stream::{self, Stream, StreamExt};
+use rand::random;
+use tokio::{
+    sync::Mutex,
+    time::{self, Interval, MissedTickBehavior},
+};
+
+use crate::{
+    client::Client, config::Config, error::SurgeError, icmp::PingOutcome, PingIdentifier,
+    PingSequence, ICMP,
+};
+
+/// Settings for [`Sweeper::sweep`].
+#[derive(Debug, Clone)]
+pub struct SweepConfig {
+    concurrency: usize,
+    rate: Option<u32>,
+    retries: u32,
+    timeout: Duration,
+    payload: Vec<u8>,
+}
+
+impl Default for SweepConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 256,
+            rate: None,
+            retries: 3,
+            timeout: Duration::from_millis(500),
+            payload: vec![0; 56],
+        }
+    }
+}
+
+impl SweepConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many hosts are probed at the same time. (default: 256)
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Send at most `rate` requests per second over the whole sweep,
+    /// retries included. (default: unlimited)
+    pub fn rate(mut self, rate: u32) -> Self {
+        self.rate = Some(rate.max(1));
+        self
+    }
+
+    /// How many more requests a host gets after one times out before it is
+    /// reported as [`HostStatus::Timeout`]. (default: 3)
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// How long to wait for the reply to each request. (default: 500ms)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Data sent in every request. (default: 56 zero bytes)
+    pub fn payload(mut self, payload: &[u8]) -> Self {
+        self.payload = payload.to_vec();
+        self
+    }
+}
+
+/// What a sweep found out about one host.
+#[derive(Debug)]
+pub enum HostStatus {
+    /// The host answered with an echo reply after `rtt`.
+    Alive { rtt: Duration },
+    /// The host or a router on the path answered with an ICMP error.
+    Unreachable(PingOutcome),
+    /// No request got an answer.
+    Timeout,
+    /// The requests could not be sent.
+    Error(SurgeError),
+}
+
+/// The result of sweeping one host.
+#[derive(Debug)]
+pub struct SweepResult {
+    pub host: IpAddr,
+    pub status: HostStatus,
+    /// How many requests were sent to the host.
+    pub sent: u32,
+}
+
+impl SweepResult {
+    pub fn is_alive(&self) -> bool {
+        matches!(self.status, HostStatus::Alive { .. })
+    }
+}
+
+/// Ping many hosts once each, like `fping`, over one client per address
+/// family.
+#[derive(Clone)]
+pub struct Sweeper {
+    v4: Option<Client>,
+    v6: Option<Client>,
+}
+
+impl Sweeper {
+    /// Create an IPv4 and an IPv6 client with the default configuration.
+    /// Fails only if neither can be created; hosts of a missing family are
+    /// then reported as [`HostStatus::Error`].
+    pub fn new() -> io::Result<Sweeper> {
+        let v4 = Client::new(&Config::default());
+        let v6 = Client::new(&Config::builder().kind(ICMP::V6).build());
+        match (v4, v6) {
+            (Err(e), Err(_)) => Err(e),
+            (v4, v6) => Ok(Sweeper::from_clients(v4.ok(), v6.ok())),
+        }
+    }
+
+    /// Sweep with existing clients, e.g. ones bound to an interface.
+    pub fn from_clients(v4: Option<Client>, v6: Option<Client>) -> Sweeper {
+        Sweeper { v4, v6 }
+    }
+
+    /// Probe every host in `hosts` and yield one result per host, in the
+    /// order they finish.
+    ///
+    /// Each host gets up to `1 + retries` echo requests, sent one after
+    /// the other until one is answered. Hosts are read from `hosts` only
+    /// as fast as the concurrency limit allows, so it can be arbitrarily
+    /// long. Hosts are not deduplicated: one listed more than once is
+    /// probed, and reported, once per occurrence.
+    pub fn sweep<I>(&self, hosts: I, config: SweepConfig) -> impl Stream<Item = SweepResult> + Unpin
+    where
+        I: IntoIterator<Item = IpAddr>,
+    {
+        let limiter = config.rate.map(|rate| {
+            // `time::interval` panics on a zero period.
+            let period = (Duration::from_secs(1) / rate).max(Duration::from_nanos(1));
+            let mut ticker = time::interval(period);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            Mutex::new(ticker)
+        });
+        let ident = PingIdentifier(random());
+        // Sequence numbers are shared by the whole sweep, so that requests to
+        // a host listed twice never look identical: ping sockets ignore the
+        // identifier.
+        let next_seq = AtomicU16::new(random());
+        let concurrency = config.concurrency;
+        let shared = Arc::new((config, limiter, next_seq));
+        Box::pin(
+            stream::iter(hosts)
+                .map(move |host| {
+                    let shared = shared.clone();
+                    async move {
+                        let (config, limiter, next_seq) = &*shared;
+                        self.probe_host(host, ident, config, limiter, next_seq)
+                            .await
+                    }
+                })
+                .buffer_unordered(concurrency),
+        )
+    }
+
+    async fn probe_host(
+        &self,
+        host: IpAddr,
+        ident: PingIdentifier,
+        config: &SweepConfig,
+        limiter: &Option<Mutex<Interval>>,
+        next_seq: &AtomicU16,
+    ) -> SweepResult {
+        // IPv4-mapped IPv6 addresses only answer over IPv4.
+        let client = match host.to_canonical() {
+            IpAddr::V4(_) => self.v4.as_ref(),
+            IpAddr::V6(_) => self.v6.as_ref(),
+        };
+        let Some(client) = client else {
+            let e = io::Error::new(io::ErrorKind::AddrNotAvailable, "no socket for this family");
+            return SweepResult {
+                host,
+                status: HostStatus::Error(e.into()),
+                sent: 0,
+            };
+        };
+
+        let mut pinger = client.pinger(host.to_canonical(), ident).await;
+        pinger.timeout(config.timeout);
+        let mut sent = 0;
+        let status = loop {
+            if let Some(limiter) = limiter {
+                limiter.lock().await.tick().await;
+            }
+            sent += 1;
+            // Wraps around.
+            let seq = PingSequence(next_seq.fetch_add(1, Ordering::Relaxed));
+            match pinger.probe(seq, &config.payload).await {
+                Ok((PingOutcome::EchoReply | PingOutcome::Corrupted { .. }, _, rtt)) => {
+                    break HostStatus::Alive { rtt };
+                }
+                Ok((outcome, _, _)) => break HostStatus::Unreachable(outcome),
+                Err(SurgeError::Timeout { .. }) if sent <= config.retries => {}
+                Err(SurgeError::Timeout { .. }) => break HostStatus::Timeout,
+                Err(e) => break HostStatus::Error(e),
+            }
+        };
+        SweepResult { host, status, sent }
+    }
+}
@@ -0,0 +1,424 @@
+//! Socket calls that neither `socket2` nor `tokio` expose, such as reading the
+//! Linux socket error queue with `recvmsg(2)`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::net::IpAddr;
+use std::{
+    net::SocketAddr,
+    time::{Instant, SystemTime},
+};
+
+/// A `sock_extended_err` read from the socket error queue.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ExtendedError {
+    pub origin: u8,
+    pub icmp_type: u8,
+    pub icmp_code: u8,
+    pub info: u32,
+    pub data: u32,
+    pub offender: Option<IpAddr>,
+}
+
+/// What `recvmsg(2)` returned besides the datagram itself.
+#[derive(Debug, Default)]
+pub(crate) struct RecvMeta {
+    pub len: usize,
+    pub addr: Option<SocketAddr>,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub extended_err: Option<ExtendedError>,
+    /// TTL or hop limit of the received packet (`IP_TTL` / `IPV6_HOPLIMIT`).
+    pub hop_limit: Option<u8>,
+    /// Type of service byte or traffic class of the received packet
+    /// (`IP_TOS` / `IPV6_TCLASS`).
+    pub tos: Option<u8>,
+    /// Local address the packet was sent to (`IPV6_PKTINFO`).
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub dst_addr: Option<IpAddr>,
+    /// Software timestamp the kernel took when the packet was received, or
+    /// when it was sent for transmit timestamps on the error queue.
+    pub timestamp: Option<SystemTime>,
+}
+
+/// Per-packet options for `sendmsg(2)` that override the socket's own.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SendMeta {
+    /// TTL or hop limit of the packet (`IP_TTL` / `IPV6_HOPLIMIT`).
+    pub hop_limit: Option<u32>,
+    /// Type of service byte or traffic class of the packet (`IP_TOS` /
+    /// `IPV6_TCLASS`).
+    pub tos: Option<u8>,
+}
+
+impl SendMeta {
+    pub fn is_empty(&self) -> bool {
+        *self == SendMeta::default()
+    }
+}
+
+/// Convert a kernel timestamp, which uses the realtime clock, into an
+/// `Instant` so it can be compared with the ones taken in userspace.
+pub(crate) fn to_instant(timestamp: SystemTime) -> Instant {
+    let now = Instant::now();
+    match SystemTime::now().duration_since(timestamp) {
+        Ok(age) => now.checked_sub(age).unwrap_or(now),
+        Err(_) => now,
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) use self::linux::*;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod linux {
+    use std::{
+        fs::File,
+        io, mem,
+        net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+        os::unix::io::{AsRawFd, RawFd},
+        path::Path,
+        ptr,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    use libc::{c_int, c_void, socklen_t};
+
+    use super::{ExtendedError, RecvMeta, SendMeta};
+    use crate::ICMP;
+
+    pub(crate) const ORIGIN_ICMP: u8 = libc::SO_EE_ORIGIN_ICMP;
+    pub(crate) const ORIGIN_ICMP6: u8 = libc::SO_EE_ORIGIN_ICMP6;
+    pub(crate) const ORIGIN_TIMESTAMPING: u8 = libc::SO_EE_ORIGIN_TIMESTAMPING;
+
+    fn setsockopt(fd: RawFd, level: c_int, name: c_int, value: c_int) -> io::Result<()> {
+        let ret = unsafe {
+            libc::setsockopt(
+                fd,
+                level,
+                name,
+                &value as *const c_int as *const c_void,
+                mem::size_of::<c_int>() as socklen_t,
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Open the network namespace `name` as mounted by `ip netns add`.
+    pub(crate) fn open_netns(name: &str) -> io::Result<File> {
+        if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid network namespace name {:?}", name),
+            ));
+        }
+        let path = Path::new("/var/run/netns").join(name);
+        File::open(&path).map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no network namespace at {}", path.display()),
+            ),
+            _ => err,
+        })
+    }
+
+    /// Move the calling thread into the network namespace `netns`
+    /// (`setns(2)`).
+    pub(crate) fn set_netns(netns: &File) -> io::Result<()> {
+        if unsafe { libc::setns(netns.as_raw_fd(), libc::CLONE_NEWNET) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Ask the kernel to queue ICMP errors on the socket error queue
+    /// (`IP_RECVERR` / `IPV6_RECVERR`).
+    pub(crate) fn set_recv_err(fd: RawFd, kind: ICMP) -> io::Result<()> {
+        match kind {
+            ICMP::V4 => setsockopt(fd, libc::SOL_IP, libc::IP_RECVERR, 1),
+            ICMP::V6 => setsockopt(fd, libc::SOL_IPV6, libc::IPV6_RECVERR, 1),
+        }
+    }
+
+    /// Set Don't Fragment on every packet, or forbid fragmenting them on
+    /// IPv6, and send even those larger than the cached path MTU
+    /// (`IP_MTU_DISCOVER` / `IPV6_MTU_DISCOVER` set to `*_PMTUDISC_PROBE`,
+    /// and `IPV6_DONTFRAG`). Packets larger than the interface MTU still fail
+    /// with `EMSGSIZE`.
+    pub(crate) fn set_dont_fragment(fd: RawFd, kind: ICMP) -> io::Result<()> {
+        match kind {
+            ICMP::V4 => setsockopt(
+                fd,
+                libc::SOL_IP,
+                libc::IP_MTU_DISCOVER,
+                libc::IP_PMTUDISC_PROBE,
+            ),
+            ICMP::V6 => {
+                setsockopt(
+                    fd,
+                    libc::SOL_IPV6,
+                    libc::IPV6_MTU_DISCOVER,
+                    libc::IPV6_PMTUDISC_PROBE,
+                )?;
+                setsockopt(fd, libc::SOL_IPV6, libc::IPV6_DONTFRAG, 1)
+            }
+        }
+    }
+
+    /// Ask the kernel for the TTL and type of service of every received IPv4
+    /// packet (`IP_RECVTTL` / `IP_RECVTOS`).
+    pub(crate) fn set_recv_ipv4_info(fd: RawFd) -> io::Result<()> {
+        setsockopt(fd, libc::SOL_IP, libc::IP_RECVTTL, 1)?;
+        setsockopt(fd, libc::SOL_IP, libc::IP_RECVTOS, 1)
+    }
+
+    /// Ask the kernel for the hop limit, traffic class and destination
+    /// address of every received IPv6 packet (`IPV6_RECVHOPLIMIT` /
+    /// `IPV6_RECVTCLASS` / `IPV6_RECVPKTINFO`).
+    pub(crate) fn set_recv_ipv6_info(fd: RawFd) -> io::Result<()> {
+        setsockopt(fd, libc::SOL_IPV6, libc::IPV6_RECVHOPLIMIT, 1)?;
+        setsockopt(fd, libc::SOL_IPV6, libc::IPV6_RECVTCLASS, 1)?;
+        setsockopt(fd, libc::SOL_IPV6, libc::IPV6_RECVPKTINFO, 1)
+    }
+
+    const TIMESTAMPING: c_int = (libc::SOF_TIMESTAMPING_SOFTWARE
+        | libc::SOF_TIMESTAMPING_RX_SOFTWARE
+        | libc::SOF_TIMESTAMPING_TX_SOFTWARE
+        | libc::SOF_TIMESTAMPING_OPT_ID
+        | libc::SOF_TIMESTAMPING_OPT_TSONLY) as c_int;
+
+    /// Enable kernel software timestamps.
+    ///
+    /// `SO_TIMESTAMPING` stamps received packets and reports the transmit time
+    /// of every sent packet on the error queue, tagged with a counter that
+    /// starts at 0 (`SOF_TIMESTAMPING_OPT_ID`). Kernels that refuse it fall
+    /// back to receive timestamps only (`SO_TIMESTAMPNS`). Returns whether
+    /// transmit timestamps are enabled.
+    pub(crate) fn set_timestamping(fd: RawFd) -> io::Result<bool> {
+        if setsockopt(fd, libc::SOL_SOCKET, libc::SO_TIMESTAMPING, TIMESTAMPING).is_ok() {
+            return Ok(true);
+        }
+        setsockopt(fd, libc::SOL_SOCKET, libc::SO_TIMESTAMPNS, 1)?;
+        Ok(false)
+    }
+
+    /// Start the transmit timestamp counter over at 0. The kernel only resets
+    /// it when `SOF_TIMESTAMPING_OPT_ID` is turned on, so turn it off first.
+    pub(crate) fn restart_timestamp_ids(fd: RawFd) -> io::Result<()> {
+        let without_id = TIMESTAMPING & !(libc::SOF_TIMESTAMPING_OPT_ID as c_int);
+        setsockopt(fd, libc::SOL_SOCKET, libc::SO_TIMESTAMPING, without_id)?;
+        setsockopt(fd, libc::SOL_SOCKET, libc::SO_TIMESTAMPING, TIMESTAMPING)
+    }
+
+    fn to_system_time(ts: libc::timespec) -> Option<SystemTime> {
+        if ts.tv_sec == 0 && ts.tv_nsec == 0 {
+            return None;
+        }
+        let since_epoch = Duration::new(u64::try_from(ts.tv_sec).ok()?, ts.tv_nsec as u32);
+        Some(UNIX_EPOCH + since_epoch)
+    }
+
+    /// Convert a raw socket address into a `SocketAddr`, if it is an IP one.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must point to at least `len` readable bytes.
+    unsafe fn to_socket_addr(addr: *const libc::sockaddr, len: usize) -> Option<SocketAddr> {
+        if len < mem::size_of::<libc::sa_family_t>() {
+            return None;
+        }
+        let family = unsafe { ptr::read_unaligned(ptr::addr_of!((*addr).sa_family)) };
+        match c_int::from(family) {
+            libc::AF_INET if len >= mem::size_of::<libc::sockaddr_in>() => {
+                let sin = unsafe { ptr::read_unaligned(addr as *const libc::sockaddr_in) };
+                Some(SocketAddr::V4(SocketAddrV4::new(
+                    Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)),
+                    u16::from_be(sin.sin_port),
+                )))
+            }
+            libc::AF_INET6 if len >= mem::size_of::<libc::sockaddr_in6>() => {
+                let sin6 = unsafe { ptr::read_unaligned(addr as *const libc::sockaddr_in6) };
+                Some(SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(sin6.sin6_addr.s6_addr),
+                    u16::from_be(sin6.sin6_port),
+                    sin6.sin6_flowinfo,
+                    sin6.sin6_scope_id,
+                )))
+            }
+            _ => None,
+        }
+    }
+
+    /// Fill in `meta` from one control message.
+    ///
+    /// # Safety
+    ///
+    /// `data` must point to at least `len` readable bytes.
+    unsafe fn decode_cmsg(
+        meta: &mut RecvMeta,
+        level: c_int,
+        typ: c_int,
+        data: *const u8,
+        len: usize,
+    ) {
+        match (level, typ) {
+            (libc::SOL_IP, libc::IP_RECVERR) | (libc::SOL_IPV6, libc::IPV6_RECVERR) => {
+                let ee_len = mem::size_of::<libc::sock_extended_err>();
+                if len < ee_len {
+                    return;
+                }
+                let err = data as *const libc::sock_extended_err;
+                let ee = unsafe { ptr::read_unaligned(err) };
+                // The offender address directly follows the extended error.
+                let offender = unsafe { to_socket_addr(libc::SO_EE_OFFENDER(err), len - ee_len) };
+                meta.extended_err = Some(ExtendedError {
+                    origin: ee.ee_origin,
+                    icmp_type: ee.ee_type,
+                    icmp_code: ee.ee_code,
+                    info: ee.ee_info,
+                    data: ee.ee_data,
+                    offender: offender.map(|addr| addr.ip()),
+                });
+            }
+            (libc::SOL_IP, libc::IP_TTL) | (libc::SOL_IPV6, libc::IPV6_HOPLIMIT)
+                if len >= mem::size_of::<c_int>() =>
+            {
+                let hop_limit = unsafe { ptr::read_unaligned(data as *const c_int) };
+                meta.hop_limit = u8::try_from(hop_limit).ok();
+            }
+            // Unlike the other options the TOS is passed as a single byte.
+            (libc::SOL_IP, libc::IP_TOS) if len >= 1 => {
+                meta.tos = Some(unsafe { *data });
+            }
+            (libc::SOL_IPV6, libc::IPV6_TCLASS) if len >= mem::size_of::<c_int>() => {
+                let traffic_class = unsafe { ptr::read_unaligned(data as *const c_int) };
+                meta.tos = u8::try_from(traffic_class).ok();
+            }
+            (libc::SOL_IPV6, libc::IPV6_PKTINFO) if len >= mem::size_of::<libc::in6_pktinfo>() => {
+                let info = unsafe { ptr::read_unaligned(data as *const libc::in6_pktinfo) };
+                meta.dst_addr = Some(Ipv6Addr::from(info.ipi6_addr.s6_addr).into());
+            }
+            // `SCM_TIMESTAMPING` carries software, deprecated and hardware
+            // timestamps in that order; only the first one is used.
+            (libc::SOL_SOCKET, libc::SCM_TIMESTAMPNS | libc::SCM_TIMESTAMPING)
+                if len >= mem::size_of::<libc::timespec>() =>
+            {
+                let ts = unsafe { ptr::read_unaligned(data as *const libc::timespec) };
+                meta.timestamp = to_system_time(ts);
+            }
+            _ => {}
+        }
+    }
+
+    /// Receive one message with `recvmsg(2)` and decode the control messages
+    /// this crate knows about.
+    pub(crate) fn recvmsg(fd: RawFd, buf: &mut [u8], flags: c_int) -> io::Result<RecvMeta> {
+        let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut c_void,
+            iov_len: buf.len(),
+        };
+        // u64 keeps the buffer aligned for `cmsghdr`.
+        let mut control = [0u64; 64];
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_name = &mut addr as *mut libc::sockaddr_storage as *mut c_void;
+        msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as socklen_t;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut c_void;
+        msg.msg_controllen = mem::size_of_val(&control) as _;
+
+        let len = unsafe { libc::recvmsg(fd, &mut msg, flags) };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut meta = RecvMeta {
+            len: len as usize,
+            addr: unsafe {
+                to_socket_addr(
+                    &addr as *const libc::sockaddr_storage as *const libc::sockaddr,
+                    msg.msg_namelen as usize,
+                )
+            },
+            ..Default::default()
+        };
+
+        let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+        while !cmsg.is_null() {
+            let (level, typ, cmsg_len) = unsafe {
+                (
+                    (*cmsg).cmsg_level,
+                    (*cmsg).cmsg_type,
+                    (*cmsg).cmsg_len as usize,
+                )
+            };
+            let data = unsafe { libc::CMSG_DATA(cmsg) };
+            let data_len = cmsg_len.saturating_sub(data as usize - cmsg as usize);
+            unsafe { decode_cmsg(&mut meta, level, typ, data, data_len) };
+            cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+        }
+
+        Ok(meta)
+    }
+
+    /// Send one datagram with `sendmsg(2)`, passing the options in `meta`
+    /// as control messages.
+    pub(crate) fn sendmsg(
+        fd: RawFd,
+        buf: &[u8],
+        target: &SocketAddr,
+        meta: &SendMeta,
+    ) -> io::Result<usize> {
+        let addr = socket2::SockAddr::from(*target);
+        let mut iov = libc::iovec {
+            iov_base: buf.as_ptr() as *mut c_void,
+            iov_len: buf.len(),
+        };
+        let options = [
+            meta.hop_limit.map(|hop_limit| match target {
+                SocketAddr::V4(_) => (libc::SOL_IP, libc::IP_TTL, hop_limit as c_int),
+                SocketAddr::V6(_) => (libc::SOL_IPV6, libc::IPV6_HOPLIMIT, hop_limit as c_int),
+            }),
+            meta.tos.map(|tos| match target {
+                SocketAddr::V4(_) => (libc::SOL_IP, libc::IP_TOS, c_int::from(tos)),
+                SocketAddr::V6(_) => (libc::SOL_IPV6, libc::IPV6_TCLASS, c_int::from(tos)),
+            }),
+        ];
+
+        // u64 keeps the buffer aligned for `cmsghdr`.
+        let mut control = [0u64; 16];
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_name = addr.as_ptr() as *mut c_void;
+        msg.msg_namelen = addr.len();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut c_void;
+        msg.msg_controllen = mem::size_of_val(&control) as _;
+
+        let data_len = mem::size_of::<c_int>() as u32;
+        let mut control_len = 0;
+        let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+        for (level, typ, value) in options.into_iter().flatten() {
+            unsafe {
+                (*cmsg).cmsg_level = level;
+                (*cmsg).cmsg_type = typ;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(data_len) as _;
+                ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut c_int, value);
+                control_len += libc::CMSG_SPACE(data_len) as usize;
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+        if control_len == 0 {
+            msg.msg_control = ptr::null_mut();
+        }
+        msg.msg_controllen = control_len as _;
+
+        let len = unsafe { libc::sendmsg(fd, &msg, 0) };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(len as usize)
+    }
+}
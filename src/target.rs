@@ -0,0 +1,491 @@
+use std::{
+    fmt,
+    io::BufRead,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use crate::error::TargetSpecError;
+
+/// One entry of a target list: an address, a prefix such as `10.0.0.0/24`
+/// or a range such as `192.168.1.10-50` or `10.0.0.250-10.0.1.5`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetSpec {
+    Addr(IpAddr),
+    /// All hosts of a prefix. The network and broadcast addresses of IPv4
+    /// prefixes, and the subnet-router anycast address of IPv6 ones, are
+    /// skipped unless the prefix is a /31, /32, /127 or /128.
+    Prefix {
+        network: IpAddr,
+        len: u8,
+    },
+    /// Every address from `first` to `last`, both included.
+    Range {
+        first: IpAddr,
+        last: IpAddr,
+    },
+}
+
+fn to_bits(addr: IpAddr) -> u128 {
+    match addr {
+        IpAddr::V4(addr) => u32::from(addr).into(),
+        IpAddr::V6(addr) => addr.into(),
+    }
+}
+
+fn from_bits(bits: u128, v6: bool) -> IpAddr {
+    if v6 {
+        Ipv6Addr::from(bits).into()
+    } else {
+        Ipv4Addr::from(bits as u32).into()
+    }
+}
+
+fn width(addr: IpAddr) -> u8 {
+    if addr.is_ipv4() {
+        32
+    } else {
+        128
+    }
+}
+
+impl TargetSpec {
+    /// All addresses the spec covers, network and broadcast included.
+    fn block(&self) -> (u128, u128) {
+        match *self {
+            TargetSpec::Addr(addr) => (to_bits(addr), to_bits(addr)),
+            TargetSpec::Prefix { network, len } => {
+                let host_bits = u32::from(width(network) - len);
+                let host_mask = u128::MAX.checked_shr(128 - host_bits).unwrap_or(0);
+                let first = to_bits(network) & !host_mask;
+                (first, first | host_mask)
+            }
+            TargetSpec::Range { first, last } => (to_bits(first), to_bits(last)),
+        }
+    }
+
+    /// The addresses the spec expands to.
+    fn hosts(&self) -> (u128, u128) {
+        let (first, last) = self.block();
+        match *self {
+            TargetSpec::Prefix { network, len } if width(network) - len > 1 => {
+                if network.is_ipv4() {
+                    (first + 1, last - 1)
+                } else {
+                    (first + 1, last)
+                }
+            }
+            _ => (first, last),
+        }
+    }
+
+    fn is_ipv6(&self) -> bool {
+        match *self {
+            TargetSpec::Addr(addr) => addr.is_ipv6(),
+            TargetSpec::Prefix { network, .. } => network.is_ipv6(),
+            TargetSpec::Range { first, .. } => first.is_ipv6(),
+        }
+    }
+
+    /// Whether `addr` is within the spec, network and broadcast included.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        if addr.is_ipv6() != self.is_ipv6() {
+            return false;
+        }
+        let (first, last) = self.block();
+        (first..=last).contains(&to_bits(addr))
+    }
+
+    /// How many addresses the spec expands to. Saturates at `u128::MAX`
+    /// for `::/0`.
+    pub fn len(&self) -> u128 {
+        let (first, last) = self.hosts();
+        (last - first).saturating_add(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// The addresses the spec expands to, generated one at a time.
+    pub fn iter(&self) -> SpecIter {
+        let (first, last) = self.hosts();
+        SpecIter {
+            next: Some(first),
+            last,
+            v6: self.is_ipv6(),
+        }
+    }
+}
+
+impl FromStr for TargetSpec {
+    type Err = TargetSpecError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let invalid = || TargetSpecError::InvalidAddress(s.to_owned());
+        if let Some((addr, len)) = s.split_once('/') {
+            let network: IpAddr = addr.parse().map_err(|_| invalid())?;
+            let len: u8 = len
+                .parse()
+                .ok()
+                .filter(|len| *len <= width(network))
+                .ok_or_else(|| TargetSpecError::InvalidPrefix(s.to_owned()))?;
+            // Normalize `10.0.0.1/24` to `10.0.0.0/24`.
+            let (first, _) = TargetSpec::Prefix { network, len }.block();
+            let network = from_bits(first, network.is_ipv6());
+            return Ok(TargetSpec::Prefix { network, len });
+        }
+        if let Some((first, last)) = s.split_once('-') {
+            let first: IpAddr = first.trim().parse().map_err(|_| invalid())?;
+            let last = last.trim();
+            let last = match last.parse::<IpAddr>() {
+                Ok(last) => last,
+                // `192.168.1.10-50` or `fd00::10-ff`: replace the last
+                // octet or group of `first`.
+                Err(_) => match first {
+                    IpAddr::V4(first) => {
+                        let octet: u8 = last.parse().map_err(|_| invalid())?;
+                        let [a, b, c, _] = first.octets();
+                        Ipv4Addr::new(a, b, c, octet).into()
+                    }
+                    IpAddr::V6(first) => {
+                        let group = u16::from_str_radix(last, 16).map_err(|_| invalid())?;
+                        let mut segments = first.segments();
+                        segments[7] = group;
+                        Ipv6Addr::from(segments).into()
+                    }
+                },
+            };
+            if first.is_ipv6() != last.is_ipv6() || to_bits(last) < to_bits(first) {
+                return Err(TargetSpecError::InvalidRange(s.to_owned()));
+            }
+            return Ok(TargetSpec::Range { first, last });
+        }
+        s.parse().map(TargetSpec::Addr).map_err(|_| invalid())
+    }
+}
+
+impl fmt::Display for TargetSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetSpec::Addr(addr) => write!(f, "{addr}"),
+            TargetSpec::Prefix { network, len } => write!(f, "{network}/{len}"),
+            TargetSpec::Range { first, last } => write!(f, "{first}-{last}"),
+        }
+    }
+}
+
+impl From<IpAddr> for TargetSpec {
+    fn from(addr: IpAddr) -> Self {
+        TargetSpec::Addr(addr)
+    }
+}
+
+/// The addresses of one [`TargetSpec`], in ascending order.
+#[derive(Debug, Clone)]
+pub struct SpecIter {
+    next: Option<u128>,
+    last: u128,
+    v6: bool,
+}
+
+impl Iterator for SpecIter {
+    type Item = IpAddr;
+
+    fn next(&mut self) -> Option<IpAddr> {
+        let next = self.next?;
+        self.next = next.checked_add(1).filter(|bits| *bits <= self.last);
+        Some(from_bits(next, self.v6))
+    }
+}
+
+impl SpecIter {
+    /// Skip every address up to and including `end`.
+    fn skip_past(&mut self, end: u128) {
+        if self.next.is_some_and(|next| next <= end) {
+            self.next = end.checked_add(1).filter(|bits| *bits <= self.last);
+        }
+    }
+}
+
+/// A list of targets to ping, expanded lazily into addresses, e.g. for
+/// [`Sweeper::sweep`](crate::Sweeper::sweep) or
+/// [`Client::pinger`](crate::Client::pinger).
+///
+/// ```rust
+/// use surge_ping::TargetList;
+///
+/// let targets = TargetList::parse_lines("10.0.0.0/24\n!10.0.0.7\n192.168.1.10-50").unwrap();
+/// assert_eq!(targets.iter().count(), 254 - 1 + 41);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TargetList {
+    include: Vec<TargetSpec>,
+    exclude: Vec<TargetSpec>,
+    limit: Option<u128>,
+}
+
+impl TargetList {
+    pub fn new() -> TargetList {
+        TargetList::default()
+    }
+
+    /// Add the addresses of `spec`.
+    pub fn include(&mut self, spec: TargetSpec) -> &mut TargetList {
+        self.include.push(spec);
+        self
+    }
+
+    /// Leave out every address within `spec`, whichever entry it comes from.
+    pub fn exclude(&mut self, spec: TargetSpec) -> &mut TargetList {
+        self.exclude.push(spec);
+        self
+    }
+
+    /// Take at most `limit` addresses from each entry, so a large IPv6
+    /// prefix such as `2001:db8::/64` can be listed without sweeping all
+    /// of it. (default: unlimited)
+    pub fn limit(&mut self, limit: u128) -> &mut TargetList {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Parse a host list with one or more targets per line, separated by
+    /// whitespace or commas. Targets prefixed with `!` are excluded, and
+    /// everything after a `#` is a comment.
+    pub fn parse_lines(text: &str) -> Result<TargetList, TargetSpecError> {
+        let mut list = TargetList::new();
+        for (index, line) in text.lines().enumerate() {
+            list.add_line(index + 1, line)?;
+        }
+        Ok(list)
+    }
+
+    /// Read a host list in the format of [`TargetList::parse_lines`], e.g.
+    /// from a file.
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<TargetList, TargetSpecError> {
+        let mut list = TargetList::new();
+        for (index, line) in reader.lines().enumerate() {
+            list.add_line(index + 1, &line?)?;
+        }
+        Ok(list)
+    }
+
+    fn add_line(&mut self, line: usize, text: &str) -> Result<(), TargetSpecError> {
+        let text = text.split('#').next().unwrap_or_default();
+        for word in text.split(|c: char| c.is_whitespace() || c == ',') {
+            if word.is_empty() {
+                continue;
+            }
+            let (excluded, word) = match word.strip_prefix('!') {
+                Some(word) => (true, word),
+                None => (false, word),
+            };
+            let spec = word.parse().map_err(|e| TargetSpecError::Line {
+                line,
+                source: Box::new(e),
+            })?;
+            if excluded {
+                self.exclude(spec);
+            } else {
+                self.include(spec);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `addr` is one of the targets.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        self.include.iter().any(|spec| spec.contains(addr))
+            && !self.exclude.iter().any(|spec| spec.contains(addr))
+    }
+
+    /// The addresses of every entry in the order they were added, without
+    /// the excluded ones.
+    pub fn iter(&self) -> Targets {
+        self.clone().into_iter()
+    }
+}
+
+impl FromStr for TargetList {
+    type Err = TargetSpecError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TargetList::parse_lines(s)
+    }
+}
+
+impl IntoIterator for TargetList {
+    type Item = IpAddr;
+    type IntoIter = Targets;
+
+    fn into_iter(self) -> Targets {
+        Targets {
+            include: self.include.into_iter(),
+            exclude: self.exclude,
+            limit: self.limit,
+            current: None,
+        }
+    }
+}
+
+/// The addresses of a [`TargetList`].
+#[derive(Debug, Clone)]
+pub struct Targets {
+    include: std::vec::IntoIter<TargetSpec>,
+    exclude: Vec<TargetSpec>,
+    limit: Option<u128>,
+    /// Addresses of the current entry and how many more it may yield.
+    current: Option<(SpecIter, Option<u128>)>,
+}
+
+impl Iterator for Targets {
+    type Item = IpAddr;
+
+    fn next(&mut self) -> Option<IpAddr> {
+        loop {
+            let Some((addrs, remaining)) = &mut self.current else {
+                let spec = self.include.next()?;
+                self.current = Some((spec.iter(), self.limit));
+                continue;
+            };
+            if *remaining == Some(0) {
+                self.current = None;
+                continue;
+            }
+            let Some(addr) = addrs.next() else {
+                self.current = None;
+                continue;
+            };
+            // Jump past the whole excluded block instead of walking it, which
+            // could take up to 2^128 steps.
+            let excluded = self
+                .exclude
+                .iter()
+                .filter(|spec| spec.contains(addr))
+                .map(|spec| spec.block().1)
+                .max();
+            if let Some(end) = excluded {
+                addrs.skip_past(end);
+                continue;
+            }
+            if let Some(remaining) = remaining {
+                *remaining -= 1;
+            }
+            return Some(addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs(list: &str) -> Vec<String> {
+        let list: TargetList = list.parse().unwrap();
+        list.iter().map(|addr| addr.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_specs() {
+        let spec: TargetSpec = "10.0.0.77/24".parse().unwrap();
+        assert_eq!(spec.to_string(), "10.0.0.0/24");
+        assert_eq!(spec.len(), 254);
+        assert!(spec.contains("10.0.0.255".parse().unwrap()));
+        assert!(!spec.contains("10.0.1.0".parse().unwrap()));
+
+        let spec: TargetSpec = "192.168.1.10-50".parse().unwrap();
+        assert_eq!(spec.to_string(), "192.168.1.10-192.168.1.50");
+        assert_eq!(spec.len(), 41);
+
+        let spec: TargetSpec = "fd00::fe-1ff".parse().unwrap();
+        assert_eq!(spec.len(), 0x102);
+
+        assert!(matches!(
+            "10.0.0.0/33".parse::<TargetSpec>(),
+            Err(TargetSpecError::InvalidPrefix(_))
+        ));
+        assert!(matches!(
+            "10.0.0.9-3".parse::<TargetSpec>(),
+            Err(TargetSpecError::InvalidRange(_))
+        ));
+        assert!(matches!(
+            "10.0.0.1-fd00::1".parse::<TargetSpec>(),
+            Err(TargetSpecError::InvalidRange(_))
+        ));
+        assert!(matches!(
+            "example.com".parse::<TargetSpec>(),
+            Err(TargetSpecError::InvalidAddress(_))
+        ));
+    }
+
+    #[test]
+    fn skip_network_and_broadcast() {
+        assert_eq!(addrs("10.0.0.0/30"), ["10.0.0.1", "10.0.0.2"]);
+        assert_eq!(addrs("10.0.0.0/31"), ["10.0.0.0", "10.0.0.1"]);
+        assert_eq!(addrs("10.0.0.9/32"), ["10.0.0.9"]);
+        assert_eq!(addrs("fd00::/126"), ["fd00::1", "fd00::2", "fd00::3"]);
+        assert_eq!(
+            addrs("255.255.255.254-255"),
+            ["255.255.255.254", "255.255.255.255"]
+        );
+    }
+
+    #[test]
+    fn expand_lazily() {
+        let list: TargetList = "10.0.0.0/8".parse().unwrap();
+        let mut targets = list.iter();
+        assert_eq!(targets.next(), Some("10.0.0.1".parse().unwrap()));
+        assert_eq!(targets.nth(255), Some("10.0.1.1".parse().unwrap()));
+
+        let spec: TargetSpec = "::/0".parse().unwrap();
+        assert_eq!(spec.len(), u128::MAX);
+    }
+
+    #[test]
+    fn host_list() {
+        let text = "
+            # lab hosts
+            10.0.0.0/29, !10.0.0.2-4
+            10.0.1.1 10.0.1.2  # more
+            !10.0.0.6
+        ";
+        assert_eq!(
+            addrs(text),
+            ["10.0.0.1", "10.0.0.5", "10.0.1.1", "10.0.1.2"]
+        );
+
+        let err = TargetList::parse_lines("10.0.0.1\n10.0.0.300").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 2: invalid address in target `10.0.0.300`"
+        );
+    }
+
+    #[test]
+    fn skip_excluded_blocks() {
+        assert_eq!(
+            addrs("2001:db8::/64\n!2001:db8::/64\n10.0.0.1"),
+            ["10.0.0.1"]
+        );
+        assert_eq!(addrs("::/0\n!::/1\n!8000::/1"), Vec::<String>::new());
+
+        let list: TargetList = "10.0.0.0/8\n!10.0.0.0/9\n!10.128.0.0-10.128.0.9"
+            .parse()
+            .unwrap();
+        let mut targets = list.iter();
+        assert_eq!(targets.next(), Some("10.128.0.10".parse().unwrap()));
+        assert_eq!(targets.next(), Some("10.128.0.11".parse().unwrap()));
+    }
+
+    #[test]
+    fn limit_per_entry() {
+        let mut list: TargetList = "2001:db8::/64\n10.0.0.0/24".parse().unwrap();
+        list.limit(2).exclude("2001:db8::1".parse().unwrap());
+        assert_eq!(
+            list.iter().map(|addr| addr.to_string()).collect::<Vec<_>>(),
+            ["2001:db8::2", "2001:db8::3", "10.0.0.1", "10.0.0.2"]
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use std::{net::IpAddr, time::Duration};
+
+use futures::{
+    future,
+    stream::{self, Stream},
+};
+
+use crate::{
+    error::{Result, SurgeError},
+    icmp::{PingOutcome, PingSequence},
+    ping::Pinger,
+};
+
+/// Settings for [`Pinger::traceroute`].
+#[derive(Debug, Clone)]
+pub struct TracerouteConfig {
+    first_hop: u8,
+    max_hops: u8,
+    probes: usize,
+    payload: Vec<u8>,
+    flow: Option<u16>,
+}
+
+impl Default for TracerouteConfig {
+    fn default() -> Self {
+        Self {
+            first_hop: 1,
+            max_hops: 30,
+            probes: 3,
+            payload: vec![0; 56],
+            flow: None,
+        }
+    }
+}
+
+impl TracerouteConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// TTL of the first hop probed. (default: 1)
+    pub fn first_hop(mut self, ttl: u8) -> Self {
+        self.first_hop = ttl.max(1);
+        self
+    }
+
+    /// Give up after probing this TTL. (default: 30)
+    pub fn max_hops(mut self, ttl: u8) -> Self {
+        self.max_hops = ttl;
+        self
+    }
+
+    /// Requests sent to each hop. (default: 3)
+    pub fn probes(mut self, probes: usize) -> Self {
+        self.probes = probes.max(1);
+        self
+    }
+
+    /// Data sent in every request. (default: 56 zero bytes)
+    pub fn payload(mut self, payload: &[u8]) -> Self {
+        self.payload = payload.to_vec();
+        self
+    }
+
+    /// Paris traceroute: give every probe the same identifier and checksum,
+    /// so routers that balance load per flow send them all down the same
+    /// path. The first two payload bytes are overwritten to make up for the
+    /// changing sequence number, so the payload must be at least that long.
+    /// `flow` picks the checksum, and so which of the balanced paths is
+    /// traced. (default: off)
+    pub fn paris(mut self, flow: u16) -> Self {
+        self.flow = Some(flow);
+        self
+    }
+}
+
+/// One answer to a traceroute probe.
+#[derive(Debug)]
+pub struct HopProbe {
+    /// The router or host that answered.
+    pub from: IpAddr,
+    pub rtt: Duration,
+    /// `TimeExceeded` from routers on the path, `EchoReply` from the
+    /// destination, or the error that ended the path.
+    pub outcome: PingOutcome,
+}
+
+/// The probes sent with one TTL, see [`Pinger::traceroute`].
+#[derive(Debug)]
+pub struct Hop {
+    pub ttl: u8,
+    /// One entry per probe, in the order they were sent, `None` for those
+    /// that timed out.
+    pub probes: Vec<Option<HopProbe>>,
+}
+
+impl Hop {
+    /// Whether the destination itself answered at this hop.
+    pub fn reached_destination(&self) -> bool {
+        self.probes
+            .iter()
+            .flatten()
+            .any(|probe| probe.outcome.is_echo_reply())
+    }
+
+    /// Whether probing further hops is pointless: anything but `Time
+    /// Exceeded` means the requests got no further than this hop.
+    fn is_last(&self) -> bool {
+        self.probes
+            .iter()
+            .flatten()
+            .any(|probe| !matches!(probe.outcome, PingOutcome::TimeExceeded { .. }))
+    }
+}
+
+/// Where a traceroute is between two hops.
+struct TraceState {
+    config: TracerouteConfig,
+    ttl: u8,
+    seq: PingSequence,
+    done: bool,
+}
+
+impl Pinger {
+    /// Trace the path to the host: send echo requests with a TTL, or hop
+    /// limit, of 1, 2, 3 and so on and yield the routers that report them
+    /// as expired, one [`Hop`] at a time.
+    ///
+    /// The probes of a hop are sent together and each waits for the
+    /// pinger's timeout. The trace ends after the hop where the host
+    /// answers, a hop answers with an error other than `Time Exceeded`, or
+    /// at `max_hops`. Socket errors end it early with an `Err`.
+    ///
+    /// The TTL is set per request, so other pingers of the client are not
+    /// affected. This is only supported on Linux and Android.
+    pub fn traceroute(
+        &self,
+        config: TracerouteConfig,
+    ) -> impl Stream<Item = Result<Hop>> + Unpin + '_ {
+        let state = TraceState {
+            ttl: config.first_hop,
+            config,
+            seq: PingSequence(0),
+            done: false,
+        };
+        Box::pin(stream::unfold(state, move |mut state| async move {
+            if state.done || state.ttl > state.config.max_hops {
+                return None;
+            }
+            let mut meta = self.send_meta;
+            meta.hop_limit = Some(state.ttl.into());
+            let mut pending = Vec::with_capacity(state.config.probes);
+            for _ in 0..state.config.probes {
+                match self
+                    .send_with(state.seq, &state.config.payload, meta, state.config.flow)
+                    .await
+                {
+                    Ok(reply) => pending.push(reply),
+                    Err(e) => {
+                        state.done = true;
+                        return Some((Err(e), state));
+                    }
+                }
+                state.seq = PingSequence(state.seq.0.wrapping_add(1));
+            }
+
+            let mut probes = Vec::with_capacity(pending.len());
+            for result in future::join_all(pending).await {
+                match result {
+                    Ok((outcome, packet, rtt)) => probes.push(Some(HopProbe {
+                        from: packet.get_source(),
+                        rtt,
+                        outcome,
+                    })),
+                    Err(SurgeError::Timeout { .. }) => probes.push(None),
+                    Err(e) => {
+                        state.done = true;
+                        return Some((Err(e), state));
+                    }
+                }
+            }
+            let hop = Hop {
+                ttl: state.ttl,
+                probes,
+            };
+            state.done = hop.is_last() || state.ttl == u8::MAX;
+            state.ttl = state.ttl.saturating_add(1);
+            Some((Ok(hop), state))
+        }))
+    }
+}
@@ -6,7 +6,7 @@ async fn test_pinger_after_client_destroyed() {
     let client = Client::new(&config).unwrap();
 
     // Create a pinger while client is alive
-    let mut pinger = client
+    let pinger = client
         .pinger("8.8.8.8".parse().unwrap(), PingIdentifier(42))
         .await;
 
@@ -31,3 +31,21 @@ async fn test_pinger_after_client_destroyed() {
         }
     }
 }
+
+#[tokio::test]
+async fn test_pinger_after_clone_dropped() {
+    let client = Client::new(&Config::default()).unwrap();
+    drop(client.clone());
+
+    let mut pinger = client
+        .pinger("127.0.0.1".parse().unwrap(), PingIdentifier(42))
+        .await;
+    pinger.timeout(tokio::time::Duration::from_millis(100));
+
+    // Other clones keep the client alive.
+    let result = pinger.ping(PingSequence(0), &[0; 8]).await;
+    assert!(
+        !matches!(result, Err(SurgeError::ClientDestroyed)),
+        "client destroyed by dropping a clone"
+    );
+}
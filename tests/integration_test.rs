@@ -262,3 +262,90 @@ async fn test_ping_unreachable_host() {
         }
     }
 }
+
+#[tokio::test]
+async fn test_stream_zero_interval() {
+    use futures::StreamExt;
+    use surge_ping::StreamConfig;
+
+    let client = Client::new(&Config::default()).unwrap();
+    let mut pinger = client
+        .pinger("127.0.0.1".parse().unwrap(), PingIdentifier(800))
+        .await;
+    pinger.timeout(Duration::from_secs(1));
+
+    let config = StreamConfig::new().interval(Duration::ZERO).count(3);
+    let results: Vec<_> = pinger.stream(config).collect().await;
+    assert_eq!(results.len(), 3);
+}
+
+#[tokio::test]
+async fn test_sweep_repeated_host() {
+    use futures::StreamExt;
+    use surge_ping::{SweepConfig, Sweeper};
+
+    let sweeper = Sweeper::new().unwrap();
+    let hosts = ["127.0.0.1", "127.0.0.1"].map(|host| host.parse().unwrap());
+    // A rate too high for a whole nanosecond between requests.
+    let config = SweepConfig::new().rate(u32::MAX);
+    let results: Vec<_> = sweeper.sweep(hosts, config).collect().await;
+    assert_eq!(results.len(), 2);
+    for result in results {
+        assert!(
+            !matches!(result.status, surge_ping::HostStatus::Error(_)),
+            "{:?}",
+            result
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_ping_ipv6_host_on_ipv4_client() {
+    let client = Client::new(&Config::default()).unwrap();
+    let mut pinger = client
+        .pinger("::1".parse().unwrap(), PingIdentifier(900))
+        .await;
+    pinger.timeout(Duration::from_secs(1));
+
+    match pinger.ping(PingSequence(0), &[0; 8]).await {
+        Err(SurgeError::AddressFamilyMismatch { host }) => {
+            assert_eq!(host, "::1".parse::<IpAddr>().unwrap());
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_dual_stack_routes_by_family() {
+    use surge_ping::DualStackClient;
+
+    let client = DualStackClient::default();
+    let socket = |host: &str| client.client(host.parse().unwrap()).unwrap().get_socket();
+    assert_eq!(socket("127.0.0.1").kind(), ICMP::V4);
+    assert_eq!(socket("::1").kind(), ICMP::V6);
+    assert_eq!(socket("::ffff:127.0.0.1").kind(), ICMP::V4);
+
+    // Each family's socket is opened once and shared afterwards.
+    let fd = |host: &str| socket(host).get_native_sock();
+    assert_eq!(fd("127.0.0.1"), fd("::ffff:127.0.0.1"));
+    assert_eq!(fd("::1"), fd("::1"));
+    assert_ne!(fd("127.0.0.1"), fd("::1"));
+}
+
+#[tokio::test]
+async fn test_dual_stack_ping_ipv4_mapped() {
+    use surge_ping::DualStackClient;
+
+    let client = DualStackClient::default();
+    let mut pinger = client
+        .pinger("::ffff:127.0.0.1".parse().unwrap(), PingIdentifier(901))
+        .await
+        .unwrap();
+    assert_eq!(pinger.host, "127.0.0.1".parse::<IpAddr>().unwrap());
+    pinger.timeout(Duration::from_secs(1));
+
+    match pinger.ping(PingSequence(0), &[0; 8]).await {
+        Ok(_) | Err(SurgeError::Timeout { .. }) => {}
+        Err(e) => panic!("Unexpected error: {:?}", e),
+    }
+}